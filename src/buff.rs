@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    net::{
+        packet::{BuffState, NetworkEvent},
+        PlayerId, PlayerPeerId,
+    },
+    simulation::SimulationSet,
+    stats::{Modifier, Stat},
+};
+
+/// Timed powerup effects. Each player's buffs are replicated so everyone sees shields and
+/// remote freezes stop enemies on the host
+#[derive(Debug)]
+pub struct BuffPlugin;
+
+impl Plugin for BuffPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, startup)
+            .add_systems(Update, (net_read, update_shields.after(net_read)))
+            .add_systems(
+                FixedUpdate,
+                (update, net_write.after(update)).in_set(SimulationSet::Update),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Buff {
+    Speed,
+    RapidFire,
+    /// Blocks contact damage
+    Shield,
+    DoubleDamage,
+    /// Stops every enemy in place
+    Freeze,
+}
+
+impl Buff {
+    pub fn label(self) -> &'static str {
+        match self {
+            Buff::Speed => "Speed",
+            Buff::RapidFire => "Rapid fire",
+            Buff::Shield => "Shield",
+            Buff::DoubleDamage => "Double damage",
+            Buff::Freeze => "Freeze",
+        }
+    }
+
+    /// Seconds the buff lasts, picking it up again restarts it
+    pub fn duration(self) -> f32 {
+        match self {
+            Buff::Speed => 10.0,
+            Buff::RapidFire => 8.0,
+            Buff::Shield => 6.0,
+            Buff::DoubleDamage => 8.0,
+            Buff::Freeze => 4.0,
+        }
+    }
+
+    /// The stat the buff changes for as long as it lasts, if any
+    pub fn modifier(self) -> Option<(Stat, Modifier)> {
+        match self {
+            Buff::Speed => Some((Stat::MaxSpeed, Modifier::Multiply(1.3))),
+            Buff::RapidFire => Some((Stat::FireRate, Modifier::Multiply(2.0))),
+            Buff::DoubleDamage => Some((Stat::Damage, Modifier::Multiply(2.0))),
+            Buff::Shield | Buff::Freeze => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveBuff {
+    pub buff: Buff,
+    /// Seconds left
+    pub remaining: f32,
+}
+
+/// Buffs active on a ship, local or remote
+#[derive(Component, Debug, Clone, Default)]
+pub struct Buffs {
+    active: Vec<ActiveBuff>,
+    /// Set when a buff starts or ends so the new state gets sent
+    changed: bool,
+}
+
+impl Buffs {
+    /// Starts the buff, or restarts it when already active
+    pub fn add(&mut self, buff: Buff) {
+        match self.active.iter_mut().find(|active| active.buff == buff) {
+            Some(active) => active.remaining = buff.duration(),
+            None => self.active.push(ActiveBuff {
+                buff,
+                remaining: buff.duration(),
+            }),
+        }
+        self.changed = true;
+    }
+
+    pub fn has(&self, buff: Buff) -> bool {
+        self.active.iter().any(|active| active.buff == buff)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ActiveBuff> {
+        self.active.iter()
+    }
+}
+
+#[derive(Component)]
+struct ShieldBubble;
+
+#[derive(Resource)]
+struct BuffAssets {
+    shield_mesh: Handle<Mesh>,
+    shield_material: Handle<StandardMaterial>,
+}
+
+fn startup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(BuffAssets {
+        shield_mesh: meshes.add(
+            Mesh::try_from(shape::Icosphere {
+                radius: 0.8,
+                subdivisions: 3,
+            })
+            .unwrap(),
+        ),
+        shield_material: materials.add(StandardMaterial {
+            base_color: Color::rgba(0.3, 0.6, 1.0, 0.25),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+fn update(time: Res<Time>, mut query: Query<&mut Buffs>) {
+    let dt = time.delta_seconds();
+    for mut buffs in &mut query {
+        let count = buffs.active.len();
+        buffs.active.retain_mut(|active| {
+            active.remaining -= dt;
+            active.remaining > 0.0
+        });
+        if buffs.active.len() != count {
+            buffs.changed = true;
+        }
+    }
+}
+
+/// Every peer sends the buffs of its own player, only when they change
+fn net_write(
+    player_id: Res<PlayerId>,
+    mut buffs: Query<&mut Buffs, Without<PlayerPeerId>>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    for mut buffs in &mut buffs {
+        if !buffs.changed {
+            continue;
+        }
+        buffs.changed = false;
+
+        if let Some(id) = player_id.0 {
+            net_event_writer.send(NetworkEvent::BuffState(BuffState {
+                id,
+                buffs: buffs.active.clone(),
+            }));
+        }
+    }
+}
+
+fn net_read(
+    mut read_buff_state: EventReader<BuffState>,
+    mut peers: Query<(&mut Buffs, &PlayerPeerId)>,
+) {
+    for buff_state in read_buff_state.read() {
+        for (mut buffs, player_peer_id) in &mut peers {
+            if player_peer_id.0 == buff_state.id {
+                buffs.active = buff_state.buffs.clone();
+            }
+        }
+    }
+}
+
+fn update_shields(
+    mut commands: Commands,
+    assets: Res<BuffAssets>,
+    ships: Query<(Entity, &Buffs)>,
+    bubbles: Query<(Entity, &Parent), With<ShieldBubble>>,
+) {
+    for (entity, buffs) in &ships {
+        let bubble = bubbles.iter().find(|(_, parent)| parent.get() == entity);
+        match (buffs.has(Buff::Shield), bubble) {
+            (true, None) => {
+                commands.entity(entity).with_children(|parent| {
+                    parent.spawn((
+                        PbrBundle {
+                            mesh: assets.shield_mesh.clone(),
+                            material: assets.shield_material.clone(),
+                            ..default()
+                        },
+                        ShieldBubble,
+                    ));
+                });
+            }
+            (false, Some((bubble, _))) => {
+                commands.entity(bubble).despawn_recursive();
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,44 +1,40 @@
-use std::time::Duration;
-
 use bevy::{
     math::{vec2, vec3},
     prelude::*,
 };
 
 use crate::{
-    enemy::Enemy,
+    enemy::{DamageDealt, Enemy},
     net::{
         packet::{BulletState, NetworkEvent},
-        PlayerPeerId, ServerState,
+        ServerState,
     },
-    player::Player,
-    powerups::{PowerupSpawnEvent, PowerupType},
+    simulation::{Interpolated, SimulationSet},
 };
 
 pub const MAX_BULLET_COUNT: usize = 1024;
+const HOMING_TURN_RATE: f32 = 6.0;
 
 #[derive(Debug)]
 pub struct BulletPlugin;
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(BulletTimer(Timer::from_seconds(0.1, TimerMode::Once)))
+        app.add_event::<BulletSpawnEvent>()
             .add_systems(Startup, startup)
+            .add_systems(Update, net_read)
             .add_systems(
-                Update,
+                FixedUpdate,
                 (
                     update,
-                    spawn_bullets,
-                    net_write.after(spawn_bullets).after(update),
-                    net_read,
-                ),
+                    spawn_bullets.after(update),
+                    net_write.after(spawn_bullets),
+                )
+                    .in_set(SimulationSet::Update),
             );
     }
 }
 
-#[derive(Resource)]
-struct BulletTimer(pub Timer);
-
 #[derive(Component, Clone)]
 pub struct Bullet {
     pub id: u32,
@@ -46,12 +42,29 @@ pub struct Bullet {
     pub ttl: f32,
     pub damage: f32,
     pub speed: f32,
+    /// Steers towards the nearest enemy
+    pub homing: bool,
+    /// The local player that fired it
+    pub owner: Option<Entity>,
 }
 
 #[derive(Bundle, Clone)]
 pub struct BulletBundle {
     pub bullet: Bullet,
     pub pbr: PbrBundle,
+    pub interpolated: Interpolated,
+}
+
+/// Takes a bullet from the pool, systems sending this must run before [`spawn_bullets`]
+#[derive(Event)]
+pub struct BulletSpawnEvent {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub speed: f32,
+    pub damage: f32,
+    pub ttl: f32,
+    pub homing: bool,
+    pub owner: Option<Entity>,
 }
 
 fn startup(
@@ -73,6 +86,8 @@ fn startup(
             ttl: 2.0,
             damage: 1.0,
             speed: 30.0,
+            homing: false,
+            owner: None,
         },
         pbr: PbrBundle {
             mesh: mesh.clone(),
@@ -81,100 +96,100 @@ fn startup(
             visibility: Visibility::Hidden,
             ..default()
         },
+        interpolated: Interpolated::default(),
     }));
 }
 
 fn update(
     mut bullets: Query<(&mut Transform, &mut Bullet, &mut Visibility), Without<Enemy>>,
-    mut enemies: Query<(&Transform, &Enemy, &mut Visibility), Without<Bullet>>,
-    mut spawn_powerup_events: EventWriter<PowerupSpawnEvent>,
+    mut enemies: Query<(&Transform, &mut Enemy, &Visibility), Without<Bullet>>,
+    mut damage_events: EventWriter<DamageDealt>,
     time: Res<Time>,
 ) {
-    for (mut transform, bullet, _) in bullets.iter_mut() {
+    let dt = time.delta_seconds();
+
+    for (mut transform, mut bullet, visibility) in bullets.iter_mut() {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        if bullet.homing {
+            let position = transform.translation.xz();
+            let target = enemies
+                .iter()
+                .filter(|(_, _, visibility)| **visibility != Visibility::Hidden)
+                .map(|(enemy_transform, _, _)| enemy_transform.translation.xz())
+                .min_by(|a, b| {
+                    a.distance_squared(position)
+                        .total_cmp(&b.distance_squared(position))
+                });
+            if let Some(target) = target {
+                let to_target = (target - position).normalize_or_zero();
+                bullet.velocity =
+                    (bullet.velocity + to_target * HOMING_TURN_RATE * dt).normalize_or_zero();
+            }
+        }
+
         transform.translation +=
-            vec3(bullet.velocity.x, 0.0, bullet.velocity.y) * bullet.speed * time.delta_seconds();
+            vec3(bullet.velocity.x, 0.0, bullet.velocity.y) * bullet.speed * dt;
     }
 
-    for (transform, _enemy, mut visibility) in enemies.iter_mut() {
+    for (transform, mut enemy, visibility) in enemies.iter_mut() {
         if *visibility == Visibility::Hidden {
             continue;
         }
-        for (bullet_transform, _bullet, mut bullet_vis) in bullets.iter_mut() {
+        for (bullet_transform, bullet, mut bullet_vis) in bullets.iter_mut() {
             if *bullet_vis == Visibility::Visible {
                 if (transform.translation.xz() - bullet_transform.translation.xz()).length() < 0.5 {
                     *bullet_vis = Visibility::Hidden;
-                    *visibility = Visibility::Hidden;
-
-                    // 5% chance to spawn a powerup
-                    if fastrand::f32() < 0.05 {
-                        spawn_powerup_events.send(PowerupSpawnEvent {
-                            powerup_type: PowerupType::random(),
-                            transform: transform.clone(),
-                        })
-                    }
+                    enemy.damage(
+                        bullet.damage,
+                        transform.translation,
+                        bullet.owner,
+                        &mut damage_events,
+                    );
                 }
             }
         }
     }
 
     for (_, mut bullet, mut visibility) in bullets.iter_mut() {
-        bullet.ttl -= time.delta_seconds();
+        bullet.ttl -= dt;
         if bullet.ttl < 0.0 {
             *visibility = Visibility::Hidden;
         }
     }
 }
 
-fn spawn_bullets(
-    time: Res<Time>,
-    keys: Res<Input<KeyCode>>,
-    mut timer: ResMut<BulletTimer>,
-    mut player: Query<(&mut Player, &Transform), Without<PlayerPeerId>>,
-    mut bullets: Query<
-        (&mut Transform, &mut Bullet, &mut Visibility),
-        (Without<Enemy>, Without<Player>),
-    >,
+pub fn spawn_bullets(
+    mut events: EventReader<BulletSpawnEvent>,
+    mut bullets: Query<(
+        &mut Transform,
+        &mut Interpolated,
+        &mut Bullet,
+        &mut Visibility,
+    )>,
 ) {
-    let (mut player, transform) = player.single_mut();
-
-    timer.0.tick(time.delta());
-    if keys.any_pressed([KeyCode::Space]) && timer.0.finished() {
-        let spread = 0.10;
-
-        player.gun = (player.gun + 1) % 2;
-
-        let mut position = transform.translation.xz();
-        let mut forward = transform.forward() * 0.3;
-        position += forward.xz();
-        forward.y = 0.0;
-        forward = forward.normalize_or_zero();
-
-        let mut side = transform.right() * 0.65;
-        if player.gun != 0 {
-            side *= -1.0;
-        }
-        position += side.xz();
-
-        let velocity = vec2(
-            forward.x + fastrand::f32() * spread - spread / 2.0,
-            forward.z + fastrand::f32() * spread - spread / 2.0,
-        );
-
-        for (mut transform, mut bullet, mut visibility) in bullets.iter_mut() {
-            if *visibility == Visibility::Hidden {
-                *visibility = Visibility::Visible;
-                transform.translation = vec3(position.x, 0.5, position.y);
-                bullet.velocity = velocity;
-                bullet.ttl = 2.0;
-                break;
-            }
-        }
-
-        timer
-            .0
-            .set_duration(Duration::from_secs_f32(0.1 / player.damage));
-
-        timer.0.reset();
+    let mut pool = bullets
+        .iter_mut()
+        .filter(|(_, _, _, visibility)| **visibility == Visibility::Hidden);
+
+    for event in events.read() {
+        // drop the shot when every bullet is in flight
+        let Some((mut transform, mut interpolated, mut bullet, mut visibility)) = pool.next()
+        else {
+            continue;
+        };
+
+        *visibility = Visibility::Visible;
+        transform.translation = vec3(event.position.x, 0.5, event.position.y);
+        interpolated.snap(&transform);
+        bullet.velocity = event.velocity;
+        bullet.speed = event.speed;
+        bullet.damage = event.damage;
+        bullet.ttl = event.ttl;
+        bullet.homing = event.homing;
+        bullet.owner = event.owner;
     }
 }
 
@@ -198,15 +213,22 @@ fn net_write(
 fn net_read(
     status: Res<ServerState>,
     mut net_event_reader: EventReader<BulletState>,
-    mut bullet_query: Query<(&mut Transform, &mut Visibility, &mut Bullet)>,
+    mut bullet_query: Query<(
+        &mut Transform,
+        &mut Interpolated,
+        &mut Visibility,
+        &mut Bullet,
+    )>,
 ) {
     if *status == ServerState::Client {
         let mut ships = bullet_query.iter_mut().collect::<Vec<_>>();
-        ships.sort_by_key(|(_, _, enemy)| enemy.id);
+        ships.sort_by_key(|(_, _, _, enemy)| enemy.id);
 
         for event in net_event_reader.read() {
-            let (transform, visibility, bullet) = ships.get_mut(event.id as usize).unwrap();
+            let (transform, interpolated, visibility, bullet) =
+                ships.get_mut(event.id as usize).unwrap();
             transform.translation = vec3(event.position.x, 0.0, event.position.y);
+            interpolated.snap(transform);
             **visibility = if event.visible {
                 Visibility::Visible
             } else {
@@ -214,6 +236,8 @@ fn net_read(
             };
             bullet.velocity = event.velocity;
             bullet.ttl = 2.0;
+            // the host's bullets, their hits are counted there
+            bullet.owner = None;
         }
     }
 }
@@ -1,31 +1,480 @@
-use bevy::{core_pipeline::tonemapping::Tonemapping, prelude::*};
+use bevy::{
+    core_pipeline::{
+        clear_color::ClearColorConfig,
+        core_2d::Camera2d,
+        core_3d::Camera3d,
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    render::camera::Viewport,
+    transform::TransformSystem,
+    utils::HashMap,
+    window::PrimaryWindow,
+};
 
-use crate::{net::PlayerPeerId, player::Player};
+use crate::{
+    enemy::EnemyDied,
+    net::PlayerPeerId,
+    player::{LocalPlayer, Player, PlayerInput, PrimaryPlayer, TeammateHealth},
+    settings::Settings,
+    simulation::SimulationSet,
+    ui::MenuState,
+};
 
-pub struct PlayerCameraPlugin;
+/// Height above the ground at normal zoom
+const DEFAULT_HEIGHT: f32 = 36.0;
+/// Degrees the camera looks down
+const DEFAULT_PITCH: f32 = 63.0;
+/// How quickly the camera catches up, higher is snappier
+const FOLLOW_STIFFNESS: f32 = 8.0;
+/// How far ahead of the ship the camera looks in the aim direction
+const LOOK_AHEAD: f32 = 4.0;
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 2.0;
+/// Zoom change per mouse wheel line
+const ZOOM_STEP: f32 = 0.1;
+/// Pixels of scrolling that count as one line on touchpads
+const PIXELS_PER_LINE: f32 = 100.0;
+/// Trauma lost per second
+const TRAUMA_DECAY: f32 = 1.5;
+/// World units the camera moves at full trauma
+const MAX_SHAKE_OFFSET: f32 = 1.5;
+/// Radians the camera rolls at full trauma
+const MAX_SHAKE_ROLL: f32 = 0.05;
+const HIT_TRAUMA: f32 = 0.4;
+const KILL_TRAUMA: f32 = 0.1;
+const BOSS_KILL_TRAUMA: f32 = 0.8;
+/// Kills further than this from the ship don't shake the camera
+const KILL_TRAUMA_DISTANCE: f32 = 20.0;
+/// How far from the center the shared camera fits on screen at normal zoom
+const FRAMED_RADIUS: f32 = 12.0;
+/// The shared camera leaves out players further than this from the ship it follows, so it can't
+/// zoom out forever
+const MAX_SPREAD: f32 = 30.0;
+/// Drawn after every player's view so the HUD covers the whole window
+const UI_CAMERA_ORDER: isize = 100;
+
+/// Follows the local ship from above, zooms with the mouse wheel and shakes on hits and kills. The
+/// shared camera setting frames every living player instead, otherwise couch co-op splits the
+/// window between the local players
+#[derive(Debug)]
+pub struct PlayerCameraPlugin {
+    /// Height above the ground at normal zoom
+    pub height: f32,
+    /// Degrees the camera looks down, 90 is straight down
+    pub pitch: f32,
+}
+
+impl Default for PlayerCameraPlugin {
+    fn default() -> Self {
+        Self {
+            height: DEFAULT_HEIGHT,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
 
 impl Plugin for PlayerCameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_camera)
-            .add_systems(PostUpdate, update_camera);
+        // level with the ground the camera would have to be infinitely far away
+        assert!(
+            self.pitch > 0.0 && self.pitch <= 90.0,
+            "camera pitch must be above 0 and at most 90 degrees, got {}",
+            self.pitch
+        );
+        let controller = CameraController {
+            seat: 0,
+            height: self.height,
+            pitch: self.pitch.to_radians(),
+            zoom: 1.0,
+            target_zoom: 1.0,
+            zoom_velocity: 0.0,
+            focus: None,
+            focus_velocity: Vec3::ZERO,
+            trauma: 0.0,
+        };
+        app.add_systems(Startup, move |mut commands: Commands| {
+            commands.spawn(Camera2dBundle {
+                camera: Camera {
+                    order: UI_CAMERA_ORDER,
+                    ..default()
+                },
+                camera_2d: Camera2d {
+                    clear_color: ClearColorConfig::None,
+                },
+                tonemapping: Tonemapping::None,
+                deband_dither: DebandDither::Disabled,
+                ..default()
+            });
+            commands.spawn(PlayerCameraBundle::new(controller.clone()));
+        })
+        .add_systems(
+            Update,
+            (zoom.run_if(in_state(MenuState::Closed)), add_trauma),
+        )
+        .add_systems(
+            PostUpdate,
+            (
+                split_screen,
+                update_camera
+                    .after(split_screen)
+                    .after(SimulationSet::Interpolate)
+                    .before(TransformSystem::TransformPropagate),
+            ),
+        );
+    }
+}
+
+#[derive(Bundle)]
+struct PlayerCameraBundle {
+    camera: Camera3dBundle,
+    controller: CameraController,
+    /// The HUD is drawn once by the UI camera, not in every player's view
+    ui: UiCameraConfig,
+}
+
+impl PlayerCameraBundle {
+    fn new(controller: CameraController) -> Self {
+        // views after the first draw next to it instead of clearing the whole window
+        let clear_color = if controller.seat == 0 {
+            ClearColorConfig::Default
+        } else {
+            ClearColorConfig::None
+        };
+        Self {
+            camera: Camera3dBundle {
+                camera: Camera {
+                    order: controller.seat as isize,
+                    ..default()
+                },
+                camera_3d: Camera3d {
+                    clear_color,
+                    ..default()
+                },
+                tonemapping: Tonemapping::None,
+                ..default()
+            },
+            controller,
+            ui: UiCameraConfig { show_ui: false },
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct CameraController {
+    /// [`LocalPlayer::seat`] of the player followed
+    pub seat: usize,
+    pub height: f32,
+    /// Radians below the horizon
+    pub pitch: f32,
+    /// Multiplies the height, the wheel changes the target and the zoom follows it
+    zoom: f32,
+    target_zoom: f32,
+    zoom_velocity: f32,
+    /// The point on the ground the camera looks at, `None` until there's a ship to follow
+    focus: Option<Vec3>,
+    focus_velocity: Vec3,
+    /// 0 to 1, the shake grows with its square
+    trauma: f32,
+}
+
+impl CameraController {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
     }
 }
 
-fn spawn_camera(mut commands: Commands) {
-    commands.spawn(Camera3dBundle {
-        tonemapping: Tonemapping::None,
+/// Where a point in the world shows up on the window through the camera, `None` behind it
+pub fn world_to_window(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    position: Vec3,
+) -> Option<Vec2> {
+    let origin = camera.logical_viewport_rect()?.min;
+    Some(camera.world_to_viewport(camera_transform, position)? + origin)
+}
+
+/// Like [`world_to_window`] through the first camera that has the point in view
+pub fn world_to_window_any<'a>(
+    cameras: impl IntoIterator<Item = (&'a Camera, &'a GlobalTransform)>,
+    position: Vec3,
+) -> Option<Vec2> {
+    cameras.into_iter().find_map(|(camera, camera_transform)| {
+        let point = world_to_window(camera, camera_transform, position)?;
+        camera
+            .logical_viewport_rect()?
+            .contains(point)
+            .then_some(point)
+    })
+}
+
+/// The point on the ground under a position on the window, seen through the camera
+pub fn window_to_world(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    position: Vec2,
+) -> Option<Vec3> {
+    let origin = camera.logical_viewport_rect()?.min;
+    let ray = camera.viewport_to_world(camera_transform, position - origin)?;
+    let distance = ray.intersect_plane(Vec3::ZERO, Vec3::Y)?;
+    Some(ray.get_point(distance))
+}
+
+/// Moves `position` towards `target` along a critically damped spring, which gets there as fast as
+/// possible without overshooting
+fn damp<T>(position: &mut T, velocity: &mut T, target: T, stiffness: f32, dt: f32)
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let x = stiffness * dt;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = *position - target;
+    let temp = (*velocity + change * stiffness) * dt;
+    *velocity = (*velocity - temp * stiffness) * decay;
+    *position = target + (change + temp) * decay;
+}
+
+/// The part of the window a seat's view takes, side by side for two players and a grid of four
+/// for more
+fn split_viewport(seat: usize, count: usize, window_size: UVec2) -> Viewport {
+    let columns = 2;
+    let rows = if count <= 2 { 1 } else { 2 };
+    let size = UVec2::new(window_size.x / columns, window_size.y / rows);
+    let cell = UVec2::new(seat as u32 % columns, seat as u32 / columns);
+    Viewport {
+        physical_position: cell * size,
+        physical_size: size,
         ..default()
-    });
+    }
+}
+
+/// Keeps one camera per local player unless the camera is shared, and lays their views out over
+/// the window
+fn split_screen(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    players: Query<&LocalPlayer>,
+    mut cameras: Query<(Entity, &mut Camera, &CameraController)>,
+) {
+    let count = if settings.shared_camera {
+        1
+    } else {
+        players.iter().count().max(1)
+    };
+
+    for (entity, _, controller) in &cameras {
+        if controller.seat >= count {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    let first = cameras
+        .iter()
+        .find(|(_, _, controller)| controller.seat == 0)
+        .map(|(_, _, controller)| controller.clone());
+    for seat in 1..count {
+        if cameras
+            .iter()
+            .any(|(_, _, controller)| controller.seat == seat)
+        {
+            continue;
+        }
+        if let Some(first) = &first {
+            commands.spawn(PlayerCameraBundle::new(CameraController {
+                seat,
+                focus: None,
+                trauma: 0.0,
+                ..first.clone()
+            }));
+        }
+    }
+
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+    let window_size = UVec2::new(window.physical_width(), window.physical_height());
+    // minimized, the views would be empty so keep the last layout until it's back
+    if window_size.cmplt(UVec2::splat(2)).any() {
+        return;
+    }
+    for (_, mut camera, controller) in &mut cameras {
+        let viewport = (count > 1).then(|| split_viewport(controller.seat, count, window_size));
+        // only touch the camera when the layout changes, so change detection stays quiet
+        let rect = |viewport: &Option<Viewport>| {
+            viewport
+                .as_ref()
+                .map(|viewport| (viewport.physical_position, viewport.physical_size))
+        };
+        if rect(&camera.viewport) != rect(&viewport) {
+            camera.viewport = viewport;
+        }
+    }
+}
+
+fn zoom(mut wheel: EventReader<MouseWheel>, mut cameras: Query<&mut CameraController>) {
+    let lines: f32 = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if lines == 0.0 {
+        return;
+    }
+    for mut camera in &mut cameras {
+        // scrolling up zooms in
+        camera.target_zoom = (camera.target_zoom - lines * ZOOM_STEP).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+}
+
+/// Shakes a player's camera when their ship is hit, when enemies die close to it and when a boss
+/// dies, the shared camera shakes for everyone
+fn add_trauma(
+    settings: Res<Settings>,
+    mut died: EventReader<EnemyDied>,
+    mut last_health: Local<HashMap<Entity, f32>>,
+    players: Query<(Entity, &Player, &Transform, &LocalPlayer)>,
+    mut cameras: Query<&mut CameraController>,
+) {
+    let died = died.read().collect::<Vec<_>>();
+    let kills = died.iter().map(|event| event.position).collect::<Vec<_>>();
+    let boss_killed = died.iter().any(|event| event.boss);
+
+    for (entity, player, transform, local) in &players {
+        let mut trauma = 0.0;
+        if last_health
+            .insert(entity, player.health)
+            .is_some_and(|health| player.health < health)
+        {
+            trauma += HIT_TRAUMA;
+        }
+        let nearby_kills = kills
+            .iter()
+            .filter(|position| position.distance(transform.translation) < KILL_TRAUMA_DISTANCE)
+            .count();
+        trauma += KILL_TRAUMA * nearby_kills as f32;
+        if boss_killed {
+            trauma += BOSS_KILL_TRAUMA;
+        }
+
+        if trauma > 0.0 {
+            for mut camera in &mut cameras {
+                if camera.seat == local.seat || settings.shared_camera {
+                    camera.add_trauma(trauma);
+                }
+            }
+        }
+    }
 }
 
 fn update_camera(
-    mut camera: Query<&mut Transform, With<Camera>>,
-    player: Query<&Transform, (With<Player>, Without<Camera>, Without<PlayerPeerId>)>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut cameras: Query<(&mut Transform, &mut CameraController)>,
+    players: Query<
+        (
+            &Transform,
+            &Player,
+            &PlayerInput,
+            &LocalPlayer,
+            Has<PrimaryPlayer>,
+        ),
+        (Without<CameraController>, Without<PlayerPeerId>),
+    >,
+    teammates: Query<(&Transform, &TeammateHealth), Without<CameraController>>,
 ) {
-    let transform = player.single();
+    let dt = time.delta_seconds();
+
+    for (mut camera_transform, mut camera) in &mut cameras {
+        let Some((transform, player, input, ..)) = players
+            .iter()
+            .find(|(_, _, _, local, _)| local.seat == camera.seat)
+        else {
+            continue;
+        };
+
+        let (target, target_zoom) = if settings.shared_camera {
+            let local = players
+                .iter()
+                .filter(|(_, player, ..)| player.health > 0.0)
+                .map(|(transform, _, _, _, primary)| (transform.translation, primary));
+            let remote = teammates
+                .iter()
+                .filter(|(_, teammate)| teammate.health > 0.0)
+                .map(|(transform, _)| (transform.translation, false));
+            // our own ship first, the others get spectated once it's gone
+            let mut living = local.chain(remote).collect::<Vec<_>>();
+            living.sort_by_key(|(_, primary)| !primary);
+            let anchor = match living.first() {
+                Some((position, _)) if player.health <= 0.0 => *position,
+                _ => transform.translation,
+            };
+            let (min, max) = living
+                .into_iter()
+                .map(|(position, _)| position)
+                .filter(|position| position.distance(anchor) <= MAX_SPREAD)
+                .fold((anchor, anchor), |(min, max), position| {
+                    (min.min(position), max.max(position))
+                });
+            let center = (min + max) / 2.0;
+            let radius = (max - min).xz().length() / 2.0;
+            let zoom = camera.target_zoom.max(radius / FRAMED_RADIUS);
+            (center, zoom.clamp(MIN_ZOOM, MAX_ZOOM))
+        } else {
+            let look_ahead = Vec3::new(input.look_dir.x, 0.0, input.look_dir.z).normalize_or_zero();
+            (
+                transform.translation + look_ahead * LOOK_AHEAD,
+                camera.target_zoom,
+            )
+        };
+
+        let CameraController {
+            focus,
+            focus_velocity,
+            zoom,
+            zoom_velocity,
+            ..
+        } = &mut *camera;
+        let focus = focus.get_or_insert(target);
+        damp(focus, focus_velocity, target, FOLLOW_STIFFNESS, dt);
+        damp(zoom, zoom_velocity, target_zoom, FOLLOW_STIFFNESS, dt);
+        let focus = *focus;
 
-    *camera.single_mut() = Transform::from_translation(
-        transform.translation + Vec3::new(0.0, 1.0, 0.5).normalize() * 40.0,
-    )
-    .looking_at(transform.translation, Vec3::NEG_Z);
+        let distance = camera.height * camera.zoom / camera.pitch.sin();
+        let offset = Vec3::new(0.0, camera.pitch.sin(), camera.pitch.cos()) * distance;
+        *camera_transform =
+            Transform::from_translation(focus + offset).looking_at(focus, Vec3::NEG_Z);
+
+        // smooth noise from a few sines, kept off the simulation's random numbers so replays
+        // match
+        camera.trauma = (camera.trauma - TRAUMA_DECAY * dt).max(0.0);
+        let shake = camera.trauma * camera.trauma;
+        let t = time.elapsed_seconds() + camera.seat as f32 * 100.0;
+        let noise = |seed: f32| ((t * 23.0 + seed).sin() + (t * 37.0 + seed * 2.0).sin()) * 0.5;
+        let right = camera_transform.right();
+        let up = camera_transform.up();
+        camera_transform.translation +=
+            (right * noise(0.0) + up * noise(10.0)) * MAX_SHAKE_OFFSET * shake;
+        camera_transform.rotate_local_z(noise(20.0) * MAX_SHAKE_ROLL * shake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::damp;
+
+    #[test]
+    fn test_damp_settles_without_overshoot() {
+        let (mut position, mut velocity) = (0.0f32, 0.0);
+        for _ in 0..120 {
+            damp(&mut position, &mut velocity, 10.0, 8.0, 1.0 / 60.0);
+            assert!(position <= 10.0);
+        }
+        assert!((position - 10.0).abs() < 0.01);
+    }
 }
@@ -1,7 +1,43 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::ship::ShipType;
+
 #[derive(Debug, Parser)]
 pub struct Cli {
     #[arg(long)]
     pub server: Option<String>,
+    /// Seed for the simulation, a random one is picked when omitted
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Simulation ticks per second
+    #[arg(long, default_value_t = 60.0, value_parser = parse_tick_rate)]
+    pub tick_rate: f64,
+    /// Record the session to a replay file
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Play back a recorded session headlessly instead of joining one
+    #[arg(long, conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+    /// Ship to fly this session instead of the one picked in the menu, must be unlocked
+    #[arg(long, value_enum)]
+    pub ship: Option<ShipType>,
+    /// Name shown to other players, instead of the one in the settings
+    #[arg(long)]
+    pub name: Option<String>,
+}
+
+/// The fixed timestep can't be zero, negative or so short that it rounds down to nothing
+pub fn check_tick_rate(tick_rate: f64) -> Result<f64, String> {
+    if tick_rate.is_finite() && (1.0..=1000.0).contains(&tick_rate) {
+        Ok(tick_rate)
+    } else {
+        Err(format!("tick rate {tick_rate} is not between 1 and 1000"))
+    }
+}
+
+fn parse_tick_rate(value: &str) -> Result<f64, String> {
+    let tick_rate = value.parse::<f64>().map_err(|error| error.to_string())?;
+    check_tick_rate(tick_rate)
 }
@@ -1,7 +1,29 @@
+pub const PLAYER_MAX_HEALTH: f32 = 100.0;
 pub const PLAYER_MAX_SPEED: f32 = 7.23;
 pub const PLAYER_ACCELERATION_RATE: f32 = 56.0;
 pub const PLAYER_DRAG_COEFFICIENT: f32 = 4.0;
+pub const PLAYER_RADIUS: f32 = 0.4;
+pub const PLAYER_INVULNERABILITY_DURATION: f32 = 0.75;
 
 pub const CHASER_MAX_SPEED: f32 = 15.85;
 pub const CHASER_ACCELERATION_RATE: f32 = 6.2;
 pub const CHASER_DRAG_COEFFICIENT: f32 = 0.01;
+pub const CHASER_RADIUS: f32 = 0.3;
+pub const CHASER_HEALTH: f32 = 2.0;
+pub const CHASER_CONTACT_DAMAGE: f32 = 5.0;
+pub const CHASER_KNOCKBACK_IMPULSE: f32 = 12.0;
+pub const CHASER_EXPERIENCE: f32 = 1.0;
+
+/// Every fifth wave is led by a boss
+pub const BOSS_WAVE_INTERVAL: usize = 5;
+pub const BOSS_HEALTH: f32 = 40.0;
+pub const BOSS_SCALE: f32 = 2.0;
+pub const BOSS_EXPERIENCE: f32 = 20.0;
+
+pub const KNOCKBACK_DECAY_RATE: f32 = 40.0;
+
+pub const PLAYER_PICKUP_RADIUS: f32 = 3.0;
+pub const PICKUP_COLLECT_DISTANCE: f32 = 1.0;
+pub const PICKUP_ACCELERATION: f32 = 30.0;
+/// Experience needed per level, level 3 takes twice as much as level 2
+pub const EXPERIENCE_PER_LEVEL: f32 = 5.0;
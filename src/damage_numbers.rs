@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+
+use crate::{
+    camera::{world_to_window_any, CameraController},
+    enemy::DamageDealt,
+};
+
+const MAX_DAMAGE_NUMBERS: usize = 64;
+/// Seconds a number stays up
+const LIFETIME: f32 = 0.8;
+/// Hits on the same enemy within this many seconds add to its number instead of stacking
+const MERGE_TIME: f32 = 0.25;
+/// World units per second
+const RISE_SPEED: f32 = 2.0;
+
+/// Numbers that float up from enemies when they're hit
+#[derive(Debug)]
+pub struct DamageNumbersPlugin;
+
+impl Plugin for DamageNumbersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, startup)
+            .add_systems(Update, (spawn_numbers, update_numbers.after(spawn_numbers)));
+    }
+}
+
+/// A text node from the pool, free while hidden
+#[derive(Component, Default)]
+struct DamageNumber {
+    id: u32,
+    position: Vec3,
+    amount: f32,
+    age: f32,
+}
+
+fn startup(mut commands: Commands, server: Res<AssetServer>) {
+    let style = TextStyle {
+        font_size: 24.0,
+        font: server.load("fonts/Roboto-Regular.ttf"),
+        color: Color::YELLOW,
+    };
+    commands.spawn_batch((0..MAX_DAMAGE_NUMBERS).map(move |_| {
+        (
+            TextBundle::from_section("", style.clone()).with_style(Style {
+                position_type: PositionType::Absolute,
+                display: Display::None,
+                ..default()
+            }),
+            DamageNumber::default(),
+        )
+    }));
+}
+
+fn spawn_numbers(
+    mut events: EventReader<DamageDealt>,
+    mut numbers: Query<(&mut DamageNumber, &mut Style)>,
+) {
+    for event in events.read() {
+        let merged = numbers.iter_mut().find(|(number, style)| {
+            style.display != Display::None && number.id == event.id && number.age < MERGE_TIME
+        });
+        if let Some((mut number, _)) = merged {
+            number.amount += event.amount;
+            number.position = event.position;
+            continue;
+        }
+
+        // when every number is up, reuse the oldest
+        let Some((mut number, mut style)) =
+            numbers.iter_mut().max_by(|(a, a_style), (b, b_style)| {
+                let a_age = if a_style.display == Display::None {
+                    f32::MAX
+                } else {
+                    a.age
+                };
+                let b_age = if b_style.display == Display::None {
+                    f32::MAX
+                } else {
+                    b.age
+                };
+                a_age.total_cmp(&b_age)
+            })
+        else {
+            continue;
+        };
+        *number = DamageNumber {
+            id: event.id,
+            position: event.position,
+            amount: event.amount,
+            age: 0.0,
+        };
+        style.display = Display::Flex;
+    }
+}
+
+fn update_numbers(
+    time: Res<Time>,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraController>>,
+    mut numbers: Query<(&mut DamageNumber, &mut Style, &mut Text)>,
+) {
+    for (mut number, mut style, mut text) in &mut numbers {
+        if style.display == Display::None {
+            continue;
+        }
+        number.age += time.delta_seconds();
+        if number.age >= LIFETIME {
+            style.display = Display::None;
+            continue;
+        }
+
+        let position = number.position + Vec3::Y * RISE_SPEED * number.age;
+        let Some(window_position) = world_to_window_any(&cameras, position) else {
+            style.display = Display::None;
+            continue;
+        };
+        style.left = Val::Px(window_position.x);
+        style.top = Val::Px(window_position.y);
+
+        let section = &mut text.sections[0];
+        // blades deal fractions of a point per tick
+        section.value = if number.amount < 1.0 {
+            format!("{:.1}", number.amount)
+        } else {
+            format!("{:.0}", number.amount)
+        };
+        section.style.color.set_a(1.0 - number.age / LIFETIME);
+    }
+}
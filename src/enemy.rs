@@ -3,16 +3,27 @@ use std::time::Duration;
 use bevy::{
     math::{vec2, vec3},
     prelude::*,
+    utils::HashMap,
 };
+use bevy_matchbox::matchbox_socket::PeerId;
 
 use crate::{
-    constants::{CHASER_ACCELERATION_RATE, CHASER_DRAG_COEFFICIENT, CHASER_MAX_SPEED},
+    buff::{Buff, Buffs},
+    constants::{
+        BOSS_EXPERIENCE, BOSS_HEALTH, BOSS_SCALE, BOSS_WAVE_INTERVAL, CHASER_ACCELERATION_RATE,
+        CHASER_CONTACT_DAMAGE, CHASER_DRAG_COEFFICIENT, CHASER_EXPERIENCE, CHASER_HEALTH,
+        CHASER_KNOCKBACK_IMPULSE, CHASER_MAX_SPEED, CHASER_RADIUS, PLAYER_INVULNERABILITY_DURATION,
+    },
+    experience::GemSpawnEvent,
     net::{
-        packet::{EnemyState, NetworkEvent},
-        ServerState,
+        packet::{EnemyHit, EnemyHits, EnemyState, NetworkEvent, WaveState},
+        PlayerId, ServerState,
     },
     player::Player,
-    ship::{Ship, ShipBundle},
+    powerups::{PowerupSpawnEvent, PowerupType},
+    scoreboard::RunStats,
+    ship::{Muzzles, Ship, ShipBundle},
+    simulation::{GameRng, Interpolated, SimulationSet},
     Materials,
 };
 
@@ -23,17 +34,23 @@ pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(SpawnGeneration(0))
+        app.add_event::<DamageDealt>()
+            .add_event::<EnemyDied>()
+            .insert_resource(SpawnGeneration(0))
+            .insert_resource(Kills(0))
             .insert_resource(SpawnTimer(Timer::from_seconds(5.0, TimerMode::Once)))
             .add_systems(Startup, startup)
+            .add_systems(Update, net_read)
             .add_systems(
-                Update,
+                FixedUpdate,
                 (
+                    apply_hits.before(update_enemy),
                     spawn_wave,
                     update_enemy,
-                    net_read,
                     net_write.after(update_enemy),
-                ),
+                    send_hits.after(update_enemy),
+                )
+                    .in_set(SimulationSet::Update),
             );
     }
 }
@@ -47,6 +64,81 @@ struct EnemyBundle {
 #[derive(Clone, Component)]
 pub struct Enemy {
     pub id: u32,
+    pub health: f32,
+    pub boss: bool,
+    /// Counts the times this slot was spawned into
+    pub generation: u16,
+    /// Damage local players did this life, clients report it to the host
+    dealt: f32,
+    /// Damage the host already took from each client's reports this life
+    applied: Vec<(PeerId, f32)>,
+    /// The local player who hit it last, `None` once a hit from anyone else lands after theirs
+    last_hit: Option<Entity>,
+}
+
+impl Enemy {
+    /// Takes health and reports the hit, `position` is where the number pops up
+    pub fn damage(
+        &mut self,
+        amount: f32,
+        position: Vec3,
+        source: Option<Entity>,
+        events: &mut EventWriter<DamageDealt>,
+    ) {
+        let landed = amount.min(self.health.max(0.0));
+        if landed > 0.0 {
+            self.last_hit = source;
+        }
+        self.health -= amount;
+        if source.is_some() {
+            self.dealt += amount;
+        }
+        events.send(DamageDealt {
+            id: self.id,
+            position,
+            amount,
+            landed,
+            source,
+        });
+    }
+
+    fn respawn(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.health = self.max_health();
+        self.dealt = 0.0;
+        self.applied.clear();
+        self.last_hit = None;
+    }
+
+    fn max_health(&self) -> f32 {
+        if self.boss {
+            BOSS_HEALTH
+        } else {
+            CHASER_HEALTH
+        }
+    }
+}
+
+/// A hit on an enemy, only used for feedback and statistics since the host decides who dies
+#[derive(Event, Debug, Clone)]
+pub struct DamageDealt {
+    /// [`Enemy::id`] of the one hit, hits close together on the same enemy share a number
+    pub id: u32,
+    pub position: Vec3,
+    pub amount: f32,
+    /// The part of `amount` the enemy still had health for
+    pub landed: f32,
+    /// The local player whose weapon hit, `None` for bullets from elsewhere
+    pub source: Option<Entity>,
+}
+
+/// An enemy ran out of health, sent on the host when it does and on clients once the host hides it
+#[derive(Event, Debug, Clone)]
+pub struct EnemyDied {
+    pub position: Vec3,
+    pub boss: bool,
+    /// The local player credited with the kill, see [`Enemy::last_hit`]
+    pub killer: Option<Entity>,
 }
 
 #[derive(Resource)]
@@ -55,16 +147,30 @@ pub struct SpawnTimer(pub Timer);
 #[derive(Resource)]
 pub struct SpawnGeneration(pub usize);
 
+/// Enemies killed this run by anyone
+#[derive(Resource)]
+pub struct Kills(pub u32);
+
 pub fn startup(mut commands: Commands, server: Res<AssetServer>, materials: ResMut<Materials>) {
     let mesh = server.load("enemy1.glb#Mesh0/Primitive0");
+    let gltf = server.load("enemy1.glb");
     let material = materials.ship_material.clone().unwrap();
     commands.spawn_batch((0..MAX_ENEMY_COUNT).map(move |i| EnemyBundle {
-        enemy: Enemy { id: i as u32 },
+        enemy: Enemy {
+            id: i as u32,
+            health: CHASER_HEALTH,
+            boss: false,
+            generation: 0,
+            dealt: 0.0,
+            applied: Vec::new(),
+            last_hit: None,
+        },
         ship: ShipBundle {
             ship: Ship::new(
                 CHASER_MAX_SPEED,
                 CHASER_ACCELERATION_RATE,
                 CHASER_DRAG_COEFFICIENT,
+                CHASER_RADIUS,
             ),
             material_mesh: MaterialMeshBundle {
                 mesh: mesh.clone(),
@@ -72,6 +178,8 @@ pub fn startup(mut commands: Commands, server: Res<AssetServer>, materials: ResM
                 visibility: Visibility::Hidden,
                 ..Default::default()
             },
+            interpolated: Interpolated::default(),
+            muzzles: Muzzles::new(gltf.clone()),
         },
     }))
 }
@@ -81,7 +189,14 @@ pub fn spawn_wave(
     status: Res<ServerState>,
     mut spawn_timer: ResMut<SpawnTimer>,
     mut spawn_generation: ResMut<SpawnGeneration>,
-    mut enemies: Query<(&mut Transform, &mut Visibility), With<Enemy>>,
+    mut rng: ResMut<GameRng>,
+    mut enemies: Query<(
+        &mut Transform,
+        &mut Interpolated,
+        &mut Ship,
+        &mut Enemy,
+        &mut Visibility,
+    )>,
 ) {
     if *status == ServerState::Host {
         if !spawn_timer.0.just_finished() {
@@ -97,12 +212,22 @@ pub fn spawn_wave(
         spawn_generation.0 += 1;
 
         let mut spawn_count = 5 * spawn_generation.0;
-        for mut enemy in enemies.iter_mut() {
-            if *enemy.1 == Visibility::Hidden {
-                *enemy.1 = Visibility::Visible;
-                enemy.0.translation = vec3(fastrand::f32() - 0.5, 0.0, fastrand::f32() - 0.5)
+        let mut boss = spawn_generation.0 % BOSS_WAVE_INTERVAL == 0;
+        for (mut transform, mut interpolated, mut ship, mut enemy, mut visibility) in
+            enemies.iter_mut()
+        {
+            if *visibility == Visibility::Hidden {
+                *visibility = Visibility::Visible;
+                enemy.boss = boss;
+                enemy.respawn();
+                let scale = if boss { BOSS_SCALE } else { 1.0 };
+                ship.radius = CHASER_RADIUS * scale;
+                transform.scale = Vec3::splat(scale);
+                transform.translation = vec3(rng.spawn.f32() - 0.5, 0.0, rng.spawn.f32() - 0.5)
                     .normalize()
                     * ARENA_SIZE;
+                interpolated.snap(&transform);
+                boss = false;
                 spawn_count -= 1;
             }
 
@@ -114,17 +239,63 @@ pub fn spawn_wave(
 }
 
 pub fn update_enemy(
-    mut players: Query<(&Transform, &mut Player), Without<Enemy>>,
-    mut enemies: Query<(&mut Ship, &Transform, &Visibility), With<Enemy>>,
+    mut players: Query<(&Transform, &mut Player, &mut Ship, &Buffs, &mut RunStats), Without<Enemy>>,
+    mut enemies: Query<(&mut Ship, &Transform, &mut Visibility, &Enemy)>,
+    buffs: Query<&Buffs>,
+    mut spawn_powerup_events: EventWriter<PowerupSpawnEvent>,
+    mut spawn_gem_events: EventWriter<GemSpawnEvent>,
+    mut died: EventWriter<EnemyDied>,
+    mut rng: ResMut<GameRng>,
+    mut kills: ResMut<Kills>,
+    status: Res<ServerState>,
     time: Res<Time>,
 ) {
     let dt = time.delta_seconds();
 
-    for (mut ship, transform, visibility) in &mut enemies {
-        if visibility != Visibility::Hidden {
+    for (_, mut player, _, _, _) in players.iter_mut() {
+        player.invulnerability = (player.invulnerability - dt).max(0.0);
+    }
+
+    // any player's freeze, local or remote, stops every enemy
+    let frozen = buffs.iter().any(|buffs| buffs.has(Buff::Freeze));
+
+    for (mut ship, transform, mut visibility, enemy) in &mut enemies {
+        // clients only learn about deaths from the host
+        let dead = enemy.health <= 0.0 && *status != ServerState::Client;
+        if *visibility != Visibility::Hidden && dead {
+            *visibility = Visibility::Hidden;
+            kills.0 += 1;
+            died.send(EnemyDied {
+                position: transform.translation,
+                boss: enemy.boss,
+                killer: enemy.last_hit,
+            });
+
+            spawn_gem_events.send(GemSpawnEvent {
+                position: transform.translation,
+                experience: if enemy.boss {
+                    BOSS_EXPERIENCE
+                } else {
+                    CHASER_EXPERIENCE
+                },
+            });
+
+            // 5% chance to spawn a powerup
+            if rng.loot.f32() < 0.05 {
+                spawn_powerup_events.send(PowerupSpawnEvent {
+                    powerup_type: PowerupType::random(&mut rng.loot),
+                    transform: *transform,
+                })
+            }
+            continue;
+        }
+
+        if *visibility != Visibility::Hidden {
             let mut direction = Vec3::ZERO;
             let mut distance = ARENA_SIZE * 10.0;
-            for (player_transform, mut player) in players.iter_mut() {
+            for (player_transform, mut player, mut player_ship, player_buffs, mut run_stats) in
+                players.iter_mut()
+            {
                 let enemy_to_player = player_transform.translation - transform.translation;
                 let enemy_to_player_len = enemy_to_player.length();
                 if enemy_to_player_len < distance {
@@ -132,12 +303,24 @@ pub fn update_enemy(
                     distance = enemy_to_player_len;
                 }
 
-                if enemy_to_player_len < 0.75 {
-                    player.health -= 1.0 * dt;
+                // ships touching, knock both apart and damage the player unless recently hit
+                let contact_distance = ship.radius + player_ship.radius + 0.05;
+                if enemy_to_player_len < contact_distance && player.invulnerability <= 0.0 {
+                    let knockback = enemy_to_player.normalize_or_zero() * CHASER_KNOCKBACK_IMPULSE;
+                    player_ship.apply_impulse(knockback);
+                    ship.apply_impulse(-knockback * 0.5);
+                    if !player_buffs.has(Buff::Shield) {
+                        player.health -= CHASER_CONTACT_DAMAGE;
+                        run_stats.damage_taken += CHASER_CONTACT_DAMAGE;
+                    }
+                    player.invulnerability = PLAYER_INVULNERABILITY_DURATION;
                 }
             }
 
-            if distance < 0.5 {
+            if frozen {
+                let velocity = ship.velocity();
+                ship.apply_impulse(-velocity);
+                ship.move_dir = Vec3::ZERO;
                 continue;
             }
 
@@ -149,34 +332,146 @@ pub fn update_enemy(
 
 fn net_write(
     status: Res<ServerState>,
+    spawn_generation: Res<SpawnGeneration>,
+    kills: Res<Kills>,
     mut net_event_writer: EventWriter<NetworkEvent>,
     ship_query: Query<(&Ship, &Transform, &Visibility, &Enemy)>,
 ) {
     if *status == ServerState::Host {
+        net_event_writer.send(NetworkEvent::WaveState(WaveState {
+            wave: spawn_generation.0 as u32,
+            kills: kills.0,
+        }));
         net_event_writer.send_batch(ship_query.iter().map(
             |(_ship, transform, visibility, enemy)| {
                 NetworkEvent::EnemyState(EnemyState {
                     id: enemy.id as u16,
                     position: vec2(transform.translation.x, transform.translation.z),
                     visible: *visibility == Visibility::Visible,
+                    boss: enemy.boss,
+                    generation: enemy.generation,
                 })
             },
         ));
     }
 }
 
+/// Clients only show their hits, the host is told about them and decides who dies
+fn send_hits(
+    status: Res<ServerState>,
+    player_id: Res<PlayerId>,
+    enemies: Query<(&Enemy, &Visibility)>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    let (ServerState::Client, Some(id)) = (&*status, player_id.0) else {
+        return;
+    };
+
+    let hits = enemies
+        .iter()
+        .filter(|(enemy, visibility)| **visibility != Visibility::Hidden && enemy.dealt > 0.0)
+        .map(|(enemy, _)| EnemyHit {
+            id: enemy.id as u16,
+            generation: enemy.generation,
+            total: enemy.dealt,
+        })
+        .collect::<Vec<_>>();
+    if !hits.is_empty() {
+        net_event_writer.send(NetworkEvent::EnemyHits(EnemyHits { id, hits }));
+    }
+}
+
+/// Takes the damage clients report, reports for an enemy that died or was respawned since are
+/// dropped
+fn apply_hits(
+    status: Res<ServerState>,
+    mut reports: EventReader<EnemyHits>,
+    mut enemies: Query<(&mut Enemy, &Visibility)>,
+) {
+    if *status != ServerState::Host {
+        reports.clear();
+        return;
+    }
+
+    let mut enemies = enemies
+        .iter_mut()
+        .filter(|(_, visibility)| **visibility != Visibility::Hidden)
+        .map(|(enemy, _)| (enemy.id as u16, enemy))
+        .collect::<HashMap<_, _>>();
+    for report in reports.read() {
+        for hit in &report.hits {
+            let Some(enemy) = enemies.get_mut(&hit.id) else {
+                continue;
+            };
+            if enemy.generation != hit.generation {
+                continue;
+            }
+            let applied = match enemy.applied.iter_mut().find(|(id, _)| *id == report.id) {
+                Some((_, applied)) => applied,
+                None => {
+                    enemy.applied.push((report.id, 0.0));
+                    &mut enemy.applied.last_mut().unwrap().1
+                }
+            };
+            let new = hit.total - *applied;
+            if new > 0.0 {
+                *applied = hit.total;
+                if enemy.health > 0.0 {
+                    enemy.last_hit = None;
+                }
+                enemy.health -= new;
+            }
+        }
+    }
+}
+
 fn net_read(
     status: Res<ServerState>,
+    mut spawn_generation: ResMut<SpawnGeneration>,
+    mut kills: ResMut<Kills>,
+    mut wave_state_reader: EventReader<WaveState>,
     mut net_event_reader: EventReader<EnemyState>,
-    mut ship_query: Query<(&mut Transform, &mut Visibility, &Enemy)>,
+    mut died: EventWriter<EnemyDied>,
+    mut ship_query: Query<(
+        &mut Transform,
+        &mut Interpolated,
+        &mut Ship,
+        &mut Visibility,
+        &mut Enemy,
+    )>,
 ) {
     if *status == ServerState::Client {
+        if let Some(event) = wave_state_reader.read().last() {
+            spawn_generation.0 = event.wave as usize;
+            kills.0 = event.kills;
+        }
+
         let mut ships = ship_query.iter_mut().collect::<Vec<_>>();
-        ships.sort_by_key(|(_, _, enemy)| enemy.id);
+        ships.sort_by_key(|(_, _, _, _, enemy)| enemy.id);
 
         for event in net_event_reader.read() {
-            let (transform, visibility, _) = ships.get_mut(event.id as usize).unwrap();
+            let (transform, interpolated, ship, visibility, enemy) =
+                ships.get_mut(event.id as usize).unwrap();
+            // the host hid it, so it died
+            if **visibility != Visibility::Hidden && !event.visible {
+                died.send(EnemyDied {
+                    position: transform.translation,
+                    boss: enemy.boss,
+                    killer: enemy.last_hit,
+                });
+            }
+            enemy.boss = event.boss;
+            // respawned by the host, hits are still counted here
+            if enemy.generation != event.generation {
+                enemy.respawn();
+                enemy.generation = event.generation;
+            }
+
+            let scale = if event.boss { BOSS_SCALE } else { 1.0 };
+            ship.radius = CHASER_RADIUS * scale;
+            transform.scale = Vec3::splat(scale);
             transform.translation = vec3(event.position.x, 0.0, event.position.y);
+            interpolated.snap(transform);
             **visibility = if event.visible {
                 Visibility::Visible
             } else {
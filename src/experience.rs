@@ -0,0 +1,385 @@
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::{
+    math::vec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_matchbox::matchbox_socket::PeerId;
+
+use crate::{
+    constants::PICKUP_COLLECT_DISTANCE,
+    enemy::update_enemy,
+    net::{
+        packet::{Disconnected, ExperienceGained, GemState, GemStates, LevelUpState, NetworkEvent},
+        read_events, PlayerId, PlayerPeerId, ServerState,
+    },
+    pickup::{attract, Pickup},
+    player::{read_input, Player, PlayerInput},
+    save::Save,
+    simulation::{GameRng, Interpolated, PauseReason, Paused, SimulationSet},
+    stats::{Stat, StatModifier, Stats},
+    ui::FeedEvent,
+    weapon::{WeaponType, Weapons, MAX_WEAPON_LEVEL},
+};
+
+const MAX_GEM_COUNT: usize = 256;
+const UPGRADE_CHOICES: usize = 3;
+
+/// Experience gems, levels and the upgrade picked at each level up. The game pauses while any
+/// connected player is still choosing
+#[derive(Debug)]
+pub struct ExperiencePlugin;
+
+impl Plugin for ExperiencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<GemSpawnEvent>()
+            .insert_resource(ChoosingPeers::default())
+            .add_systems(Startup, startup)
+            // before the ticks of the frame, so a level up holds them right away
+            .add_systems(
+                PreUpdate,
+                (net_read, level_up, choose_upgrade, pause)
+                    .chain()
+                    .after(read_events)
+                    .after(read_input),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    spawn_gems.after(update_enemy),
+                    collect_gems.after(spawn_gems).after(attract),
+                    net_write.after(collect_gems),
+                )
+                    .in_set(SimulationSet::Update),
+            );
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct Gem {
+    pub id: u32,
+    pub experience: f32,
+}
+
+#[derive(Bundle, Clone)]
+struct GemBundle {
+    gem: Gem,
+    pickup: Pickup,
+    pbr: PbrBundle,
+    interpolated: Interpolated,
+}
+
+/// Drops a gem from the pool, systems sending this must run before [`spawn_gems`]
+#[derive(Event)]
+pub struct GemSpawnEvent {
+    pub position: Vec3,
+    pub experience: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upgrade {
+    /// Equips a new weapon, or levels up an equipped one
+    Weapon(WeaponType),
+    Stat(Stat),
+}
+
+impl Upgrade {
+    pub fn label(self, weapons: &Weapons) -> String {
+        match self {
+            Upgrade::Weapon(weapon_type) => match weapons.get(weapon_type) {
+                Some(weapon) => format!("{} level {}", weapon_type.label(), weapon.level + 1),
+                None => format!("New weapon: {}", weapon_type.label()),
+            },
+            Upgrade::Stat(stat) => stat.upgrade().label(stat),
+        }
+    }
+
+    fn apply(self, player: &mut Player, stats: &mut Stats, weapons: &mut Weapons) {
+        match self {
+            Upgrade::Weapon(weapon_type) => weapons.add(weapon_type),
+            Upgrade::Stat(stat) => {
+                let max_health = stats.get(Stat::MaxHealth);
+                stats.add(StatModifier::permanent(stat, stat.upgrade()));
+                // raising max health fills the new room
+                player.health += stats.get(Stat::MaxHealth) - max_health;
+            }
+        }
+    }
+}
+
+/// Present while a local player is picking an upgrade
+#[derive(Debug, Resource)]
+pub struct LevelUp {
+    /// The player choosing, in couch co-op the others wait their turn
+    pub player: Entity,
+    pub choices: Vec<Upgrade>,
+}
+
+/// Remote players that haven't picked their upgrade yet
+#[derive(Debug, Default, Resource)]
+pub struct ChoosingPeers(pub HashSet<PeerId>);
+
+fn startup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mesh = meshes.add(shape::Cube::new(0.25).into());
+    let material = materials.add(StandardMaterial {
+        base_color: Color::GREEN,
+        unlit: true,
+        ..default()
+    });
+
+    // stood on a corner so it reads as a gem
+    let rotation = Quat::from_rotation_x(FRAC_PI_4) * Quat::from_rotation_z(FRAC_PI_4);
+    commands.spawn_batch((0..MAX_GEM_COUNT).map(move |i| GemBundle {
+        gem: Gem {
+            id: i as u32,
+            experience: 0.0,
+        },
+        pickup: Pickup::default(),
+        pbr: PbrBundle {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            transform: Transform::from_rotation(rotation),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        interpolated: Interpolated::default(),
+    }));
+}
+
+fn spawn_gems(
+    mut events: EventReader<GemSpawnEvent>,
+    mut gems: Query<(
+        &mut Transform,
+        &mut Interpolated,
+        &mut Gem,
+        &mut Pickup,
+        &mut Visibility,
+    )>,
+) {
+    let mut pool = gems
+        .iter_mut()
+        .filter(|(_, _, _, _, visibility)| **visibility == Visibility::Hidden);
+
+    for event in events.read() {
+        // the map is already full of gems, this one is lost
+        let Some((mut transform, mut interpolated, mut gem, mut pickup, mut visibility)) =
+            pool.next()
+        else {
+            continue;
+        };
+
+        *visibility = Visibility::Visible;
+        transform.translation = vec3(event.position.x, 0.0, event.position.z);
+        interpolated.snap(&transform);
+        gem.experience = event.experience;
+        pickup.reset();
+    }
+}
+
+/// The host decides who picked up a gem and tells remote players what they gained
+fn collect_gems(
+    status: Res<ServerState>,
+    mut gems: Query<(&Transform, &Gem, &mut Visibility)>,
+    mut players: Query<(&Transform, &mut Player), Without<PlayerPeerId>>,
+    peers: Query<(&Transform, &PlayerPeerId)>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    if *status == ServerState::Client {
+        return;
+    }
+
+    for (transform, gem, mut visibility) in &mut gems {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+        let position = transform.translation.xz();
+
+        if let Some((_, mut player)) = players.iter_mut().find(|(player_transform, _)| {
+            player_transform.translation.xz().distance(position) < PICKUP_COLLECT_DISTANCE
+        }) {
+            player.experience += gem.experience;
+            *visibility = Visibility::Hidden;
+        } else if let Some((_, peer_id)) = peers.iter().find(|(peer_transform, _)| {
+            peer_transform.translation.xz().distance(position) < PICKUP_COLLECT_DISTANCE
+        }) {
+            net_event_writer.send(NetworkEvent::ExperienceGained(ExperienceGained {
+                id: peer_id.0,
+                experience: gem.experience,
+            }));
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+fn net_write(
+    status: Res<ServerState>,
+    gems: Query<(&Transform, &Visibility, &Gem)>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    if *status == ServerState::Host {
+        let gems = gems
+            .iter()
+            .filter(|(_, visibility, _)| **visibility != Visibility::Hidden)
+            .map(|(transform, _, gem)| GemState {
+                id: gem.id as u16,
+                position: transform.translation.xz(),
+            })
+            .collect();
+        net_event_writer.send(NetworkEvent::GemStates(GemStates { gems }));
+    }
+}
+
+fn net_read(
+    status: Res<ServerState>,
+    player_id: Res<PlayerId>,
+    mut choosing: ResMut<ChoosingPeers>,
+    mut gem_states: EventReader<GemStates>,
+    mut experience_gained: EventReader<ExperienceGained>,
+    mut level_up_states: EventReader<LevelUpState>,
+    mut disconnected: EventReader<Disconnected>,
+    mut feed_events: EventWriter<FeedEvent>,
+    mut gems: Query<(&mut Transform, &mut Interpolated, &mut Visibility, &Gem)>,
+    mut players: Query<&mut Player, Without<PlayerPeerId>>,
+) {
+    if *status == ServerState::Client {
+        // only the newest tick matters, it lists every gem on the map
+        if let Some(event) = gem_states.read().last() {
+            let positions = event
+                .gems
+                .iter()
+                .map(|state| (state.id as u32, state.position))
+                .collect::<HashMap<_, _>>();
+            for (mut transform, mut interpolated, mut visibility, gem) in &mut gems {
+                let Some(position) = positions.get(&gem.id) else {
+                    visibility.set_if_neq(Visibility::Hidden);
+                    continue;
+                };
+                transform.translation = vec3(position.x, 0.0, position.y);
+                interpolated.snap(&transform);
+                visibility.set_if_neq(Visibility::Visible);
+            }
+        }
+    }
+
+    for event in experience_gained.read() {
+        if Some(event.id) == player_id.0 {
+            for mut player in &mut players {
+                player.experience += event.experience;
+            }
+        }
+    }
+
+    for event in level_up_states.read() {
+        if event.choosing {
+            choosing.0.insert(event.id);
+            feed_events.send(FeedEvent::TeammateLevelUp(event.id));
+        } else {
+            choosing.0.remove(&event.id);
+        }
+    }
+
+    // nobody waits on a player that left
+    for event in disconnected.read() {
+        choosing.0.remove(&event.peer_id);
+    }
+}
+
+fn level_up(
+    mut commands: Commands,
+    level_up: Option<Res<LevelUp>>,
+    player_id: Res<PlayerId>,
+    save: Res<Save>,
+    mut rng: ResMut<GameRng>,
+    mut players: Query<(Entity, &mut Player, &Weapons), Without<PlayerPeerId>>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+    mut feed_events: EventWriter<FeedEvent>,
+) {
+    // one level at a time, the next one is offered once this choice is made
+    if level_up.is_some() {
+        return;
+    }
+
+    for (entity, mut player, weapons) in &mut players {
+        let required = player.experience_to_level_up();
+        if player.experience < required {
+            continue;
+        }
+        player.experience -= required;
+        player.level += 1;
+        info!(level = player.level, "level up");
+        feed_events.send(FeedEvent::LevelUp(player.level));
+
+        let mut choices = WeaponType::ALL
+            .into_iter()
+            .filter(|weapon_type| {
+                (save.has_weapon(*weapon_type) || weapons.get(*weapon_type).is_some())
+                    && !weapons
+                        .get(*weapon_type)
+                        .is_some_and(|weapon| weapon.level >= MAX_WEAPON_LEVEL)
+            })
+            .map(Upgrade::Weapon)
+            .chain(Stat::UPGRADES.map(Upgrade::Stat))
+            .collect::<Vec<_>>();
+        rng.upgrades.shuffle(&mut choices);
+        choices.truncate(UPGRADE_CHOICES);
+        commands.insert_resource(LevelUp {
+            player: entity,
+            choices,
+        });
+
+        if let Some(id) = player_id.0 {
+            net_event_writer.send(NetworkEvent::LevelUpState(LevelUpState {
+                id,
+                choosing: true,
+            }));
+        }
+        break;
+    }
+}
+
+fn choose_upgrade(
+    mut commands: Commands,
+    level_up: Option<Res<LevelUp>>,
+    player_id: Res<PlayerId>,
+    mut players: Query<
+        (&mut Player, &mut Stats, &mut Weapons, &PlayerInput),
+        Without<PlayerPeerId>,
+    >,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    let Some(level_up) = level_up else {
+        return;
+    };
+
+    if let Ok((mut player, mut stats, mut weapons, input)) = players.get_mut(level_up.player) {
+        let Some(upgrade) = input
+            .upgrade
+            .and_then(|index| level_up.choices.get(index).copied())
+        else {
+            return;
+        };
+
+        info!(?upgrade, "upgrade chosen");
+        upgrade.apply(&mut player, &mut stats, &mut weapons);
+        commands.remove_resource::<LevelUp>();
+
+        if let Some(id) = player_id.0 {
+            net_event_writer.send(NetworkEvent::LevelUpState(LevelUpState {
+                id,
+                choosing: false,
+            }));
+        }
+    }
+}
+
+fn pause(level_up: Option<Res<LevelUp>>, choosing: Res<ChoosingPeers>, mut paused: ResMut<Paused>) {
+    paused.set(
+        PauseReason::LevelUp,
+        level_up.is_some() || !choosing.0.is_empty(),
+    );
+}
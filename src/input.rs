@@ -0,0 +1,459 @@
+use bevy::{
+    ecs::system::SystemParam,
+    input::InputSystem,
+    prelude::*,
+    utils::{HashMap, HashSet},
+    window::PrimaryWindow,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{player::LocalPlayer, settings::Settings};
+
+/// Sticks closer to center than this count as released
+const STICK_DEADZONE: f32 = 0.2;
+/// Pixels dragged from where a touch started to reach full speed
+const TOUCH_STICK_RADIUS: f32 = 60.0;
+
+/// Reads keyboard, mouse, gamepads and touch into [`ActionState`] so gameplay doesn't care which
+/// device is used. Key and button bindings live in the [`Settings`]. The resource has every
+/// device for the menus, each local player's component only has theirs
+#[derive(Debug)]
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ActionState::default())
+            .add_systems(PreUpdate, read_actions.after(InputSystem));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    /// The aim actions turn the ship in steps and fire, like the right stick
+    AimUp,
+    AimDown,
+    AimLeft,
+    AimRight,
+    Fire,
+    FireMode,
+    Pause,
+    /// Leaves the open menu screen and cancels rebinding, does nothing outside the menus
+    Back,
+    /// Opens the unlock shop
+    Interact,
+    Upgrade1,
+    Upgrade2,
+    Upgrade3,
+}
+
+impl Action {
+    pub const ALL: [Action; 16] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::AimUp,
+        Action::AimDown,
+        Action::AimLeft,
+        Action::AimRight,
+        Action::Fire,
+        Action::FireMode,
+        Action::Pause,
+        Action::Back,
+        Action::Interact,
+        Action::Upgrade1,
+        Action::Upgrade2,
+        Action::Upgrade3,
+    ];
+
+    /// Pick the level up choice at the same index
+    pub const UPGRADES: [Action; 3] = [Action::Upgrade1, Action::Upgrade2, Action::Upgrade3];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::AimUp => "Aim up",
+            Action::AimDown => "Aim down",
+            Action::AimLeft => "Aim left",
+            Action::AimRight => "Aim right",
+            Action::Fire => "Fire",
+            Action::FireMode => "Fire mode",
+            Action::Pause => "Pause",
+            Action::Back => "Menu back",
+            Action::Interact => "Unlocks",
+            Action::Upgrade1 => "Upgrade 1",
+            Action::Upgrade2 => "Upgrade 2",
+            Action::Upgrade3 => "Upgrade 3",
+        }
+    }
+
+    /// Pause already goes back in the menus, so the two can share keys
+    fn can_share(self, other: Action) -> bool {
+        matches!(
+            (self, other),
+            (Action::Pause, Action::Back) | (Action::Back, Action::Pause)
+        )
+    }
+
+    fn default_bindings(self) -> Vec<Binding> {
+        use Binding::{Gamepad, Key};
+        match self {
+            Action::MoveUp => vec![
+                Key(KeyCode::W),
+                Key(KeyCode::Up),
+                Gamepad(GamepadButtonType::DPadUp),
+            ],
+            Action::MoveDown => vec![
+                Key(KeyCode::S),
+                Key(KeyCode::Down),
+                Gamepad(GamepadButtonType::DPadDown),
+            ],
+            Action::MoveLeft => vec![
+                Key(KeyCode::A),
+                Key(KeyCode::Left),
+                Gamepad(GamepadButtonType::DPadLeft),
+            ],
+            Action::MoveRight => vec![
+                Key(KeyCode::D),
+                Key(KeyCode::Right),
+                Gamepad(GamepadButtonType::DPadRight),
+            ],
+            // the right stick aims on gamepads
+            Action::AimUp => vec![Key(KeyCode::I)],
+            Action::AimDown => vec![Key(KeyCode::K)],
+            Action::AimLeft => vec![Key(KeyCode::J)],
+            Action::AimRight => vec![Key(KeyCode::L)],
+            Action::Fire => vec![
+                Key(KeyCode::Space),
+                Gamepad(GamepadButtonType::RightTrigger2),
+            ],
+            Action::FireMode => vec![Key(KeyCode::F), Gamepad(GamepadButtonType::LeftThumb)],
+            Action::Pause => vec![Key(KeyCode::Escape), Gamepad(GamepadButtonType::Start)],
+            Action::Back => vec![Key(KeyCode::Escape), Gamepad(GamepadButtonType::East)],
+            Action::Interact => vec![Key(KeyCode::Tab), Gamepad(GamepadButtonType::Select)],
+            Action::Upgrade1 => vec![Key(KeyCode::Key1), Gamepad(GamepadButtonType::West)],
+            Action::Upgrade2 => vec![Key(KeyCode::Key2), Gamepad(GamepadButtonType::North)],
+            Action::Upgrade3 => vec![Key(KeyCode::Key3), Gamepad(GamepadButtonType::South)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    pub fn label(self) -> String {
+        match self {
+            Binding::Key(key) => format!("{key:?}"),
+            Binding::Mouse(button) => format!("Mouse {button:?}"),
+            Binding::Gamepad(button) => format!("Pad {button:?}"),
+        }
+    }
+
+    /// Keyboard and mouse bindings are one kind of device, gamepad bindings the other
+    pub fn gamepad(self) -> bool {
+        matches!(self, Binding::Gamepad(_))
+    }
+}
+
+/// What each action is bound to, actions missing from the settings file keep their defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bindings(HashMap<Action, Vec<Binding>>);
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> Vec<Binding> {
+        self.0
+            .get(&action)
+            .cloned()
+            .unwrap_or_else(|| action.default_bindings())
+    }
+
+    /// Replaces what the action was bound to on the same kind of device, keeping the others.
+    /// Other actions bound to the same key or button lose it, and take the replaced ones if that
+    /// left them nothing on that kind of device. Returns the actions it was taken from
+    pub fn set(&mut self, action: Action, binding: Binding) -> Vec<Action> {
+        let mut bindings = self.get(action);
+        let (replaced, mut bindings): (Vec<_>, Vec<_>) = bindings
+            .drain(..)
+            .partition(|bound| bound.gamepad() == binding.gamepad());
+        bindings.push(binding);
+        self.0.insert(action, bindings);
+
+        let mut taken_from = Vec::new();
+        for other in Action::ALL {
+            let mut other_bindings = self.get(other);
+            if other == action || other.can_share(action) || !other_bindings.contains(&binding) {
+                continue;
+            }
+            other_bindings.retain(|bound| *bound != binding);
+            if !other_bindings
+                .iter()
+                .any(|bound| bound.gamepad() == binding.gamepad())
+            {
+                // only ones nothing else is bound to, so the swap doesn't make a new conflict
+                let free = replaced.iter().filter(|old| {
+                    Action::ALL
+                        .iter()
+                        .all(|bound| !self.get(*bound).contains(old))
+                });
+                other_bindings.extend(free);
+            }
+            self.0.insert(other, other_bindings);
+            taken_from.push(other);
+        }
+        taken_from
+    }
+
+    pub fn label(&self, action: Action) -> String {
+        self.get(action)
+            .into_iter()
+            .map(Binding::label)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Where the player aims, if anywhere
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Aim {
+    #[default]
+    None,
+    /// A point on the screen, like the mouse cursor
+    Cursor(Vec2),
+    /// A direction on the ground plane, like the right stick
+    Direction(Vec2),
+}
+
+#[derive(Debug, Default, Clone, Resource, Component)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    /// On the ground plane with y towards the bottom of the screen, at most 1 long
+    pub move_dir: Vec2,
+    pub aim: Aim,
+    /// Level up choice picked with one of [`Action::UPGRADES`]
+    pub upgrade: Option<usize>,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// Every input device, read through [`Devices::read`]
+#[derive(SystemParam)]
+pub struct Devices<'w, 's> {
+    keys: Res<'w, Input<KeyCode>>,
+    mouse_buttons: Res<'w, Input<MouseButton>>,
+    gamepads: Res<'w, Gamepads>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    touches: Res<'w, Touches>,
+    window: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+pub fn read_actions(
+    settings: Res<Settings>,
+    devices: Devices,
+    mut state: ResMut<ActionState>,
+    mut players: Query<(&LocalPlayer, &mut ActionState)>,
+) {
+    *state = devices.read(&settings.bindings, true, |_| true);
+
+    let claimed = players
+        .iter()
+        .filter_map(|(local, _)| local.gamepad)
+        .collect::<Vec<_>>();
+    for (local, mut player_state) in &mut players {
+        *player_state = match local.gamepad {
+            Some(own) => devices.read(&settings.bindings, false, |gamepad| gamepad == own),
+            None => devices.read(&settings.bindings, true, |gamepad| {
+                !claimed.contains(&gamepad)
+            }),
+        };
+    }
+}
+
+impl Devices<'_, '_> {
+    /// Any bound key or button presses its action, twin-stick aiming and touching the right half
+    /// of the screen also fire. Only the gamepads passing `gamepad_filter` count, and the
+    /// keyboard, mouse and touch only with `pointer`
+    fn read(
+        &self,
+        bindings: &Bindings,
+        pointer: bool,
+        gamepad_filter: impl Fn(Gamepad) -> bool,
+    ) -> ActionState {
+        let gamepads = || {
+            self.gamepads
+                .iter()
+                .filter(|gamepad| gamepad_filter(*gamepad))
+        };
+        let pressed = |binding: Binding| match binding {
+            Binding::Key(key) => pointer && self.keys.pressed(key),
+            Binding::Mouse(button) => pointer && self.mouse_buttons.pressed(button),
+            Binding::Gamepad(button_type) => gamepads().any(|gamepad| {
+                self.gamepad_buttons
+                    .pressed(GamepadButton::new(gamepad, button_type))
+            }),
+        };
+        let just_pressed = |binding: Binding| match binding {
+            Binding::Key(key) => pointer && self.keys.just_pressed(key),
+            Binding::Mouse(button) => pointer && self.mouse_buttons.just_pressed(button),
+            Binding::Gamepad(button_type) => gamepads().any(|gamepad| {
+                self.gamepad_buttons
+                    .just_pressed(GamepadButton::new(gamepad, button_type))
+            }),
+        };
+
+        let mut state = ActionState::default();
+        for action in Action::ALL {
+            let action_bindings = bindings.get(action);
+            if action_bindings.iter().any(|binding| pressed(*binding)) {
+                state.pressed.insert(action);
+            }
+            if action_bindings.iter().any(|binding| just_pressed(*binding)) {
+                state.just_pressed.insert(action);
+            }
+        }
+
+        let mut move_dir = Vec2::ZERO;
+        for (action, direction) in [
+            (Action::MoveUp, Vec2::NEG_Y),
+            (Action::MoveDown, Vec2::Y),
+            (Action::MoveLeft, Vec2::NEG_X),
+            (Action::MoveRight, Vec2::X),
+        ] {
+            if state.pressed(action) {
+                move_dir += direction;
+            }
+        }
+
+        let stick = |x, y| {
+            gamepads()
+                .map(|gamepad| {
+                    let axis = |axis_type| {
+                        self.axes
+                            .get(GamepadAxis::new(gamepad, axis_type))
+                            .unwrap_or(0.0)
+                    };
+                    // stick y points up the screen
+                    Vec2::new(axis(x), -axis(y))
+                })
+                .find(|stick| stick.length() > STICK_DEADZONE)
+        };
+        if let Some(left) = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY) {
+            move_dir = left;
+        }
+
+        if pointer {
+            let window = self.window.get_single().ok();
+            if let Some(cursor) = window.and_then(Window::cursor_position) {
+                state.aim = Aim::Cursor(cursor);
+            }
+
+            // left half of the screen is a stick centered wherever the touch started, the right
+            // half aims and fires at the touch
+            let half_width = window.map_or(0.0, |window| window.width() * 0.5);
+            for touch in self.touches.iter() {
+                if touch.start_position().x < half_width {
+                    move_dir = (touch.position() - touch.start_position()) / TOUCH_STICK_RADIUS;
+                } else {
+                    state.aim = Aim::Cursor(touch.position());
+                    state.pressed.insert(Action::Fire);
+                }
+            }
+        }
+
+        let mut aim_dir = Vec2::ZERO;
+        for (action, direction) in [
+            (Action::AimUp, Vec2::NEG_Y),
+            (Action::AimDown, Vec2::Y),
+            (Action::AimLeft, Vec2::NEG_X),
+            (Action::AimRight, Vec2::X),
+        ] {
+            if state.pressed(action) {
+                aim_dir += direction;
+            }
+        }
+        if aim_dir != Vec2::ZERO {
+            state.aim = Aim::Direction(aim_dir.normalize());
+            state.pressed.insert(Action::Fire);
+        }
+
+        if let Some(right) = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY) {
+            state.aim = Aim::Direction(right);
+            state.pressed.insert(Action::Fire);
+        }
+
+        state.move_dir = move_dir.clamp_length_max(1.0);
+        state.upgrade = Action::UPGRADES
+            .iter()
+            .position(|action| state.just_pressed(*action));
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{Action, Binding, Bindings};
+
+    #[test]
+    fn test_rebinding_keeps_other_devices() {
+        let mut bindings = Bindings::default();
+        bindings.set(Action::Fire, Binding::Key(KeyCode::J));
+        assert_eq!(
+            bindings.get(Action::Fire),
+            vec![
+                Binding::Gamepad(GamepadButtonType::RightTrigger2),
+                Binding::Key(KeyCode::J),
+            ]
+        );
+
+        bindings.set(Action::Fire, Binding::Mouse(MouseButton::Left));
+        bindings.set(Action::Fire, Binding::Gamepad(GamepadButtonType::South));
+        assert_eq!(
+            bindings.get(Action::Fire),
+            vec![
+                Binding::Mouse(MouseButton::Left),
+                Binding::Gamepad(GamepadButtonType::South),
+            ]
+        );
+    }
+    #[test]
+    fn test_rebinding_takes_the_key_from_other_actions() {
+        let mut bindings = Bindings::default();
+        let taken_from = bindings.set(Action::Fire, Binding::Key(KeyCode::F));
+        assert_eq!(taken_from, vec![Action::FireMode]);
+        // fire mode gets the key fire had before
+        assert_eq!(
+            bindings.get(Action::FireMode),
+            vec![
+                Binding::Gamepad(GamepadButtonType::LeftThumb),
+                Binding::Key(KeyCode::Space),
+            ]
+        );
+
+        // pause and back are meant to share escape
+        let taken_from = bindings.set(Action::Back, Binding::Key(KeyCode::Escape));
+        assert!(taken_from.is_empty());
+    }
+}
@@ -1,28 +1,62 @@
+mod buff;
 mod bullet;
 mod camera;
 mod cli;
 mod constants;
+mod damage_numbers;
 mod enemy;
+mod experience;
+mod input;
 mod materials;
 mod net;
+mod pickup;
 mod player;
 mod powerups;
+mod save;
+mod scoreboard;
+mod settings;
 mod ship;
+mod simulation;
+mod stats;
 mod ui;
 mod util;
+mod weapon;
 
-use bevy::{math::vec3, prelude::*};
+use std::time::Duration;
+
+use bevy::{
+    app::ScheduleRunnerPlugin,
+    math::vec3,
+    prelude::*,
+    render::{settings::WgpuSettings, RenderPlugin},
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use buff::BuffPlugin;
 use bullet::BulletPlugin;
 use camera::PlayerCameraPlugin;
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use cli::Cli;
+use damage_numbers::DamageNumbersPlugin;
 use enemy::EnemyPlugin;
+use experience::ExperiencePlugin;
+use input::ActionsPlugin;
 use materials::{GridMaterial, ShipMaterial, SpaceMaterial};
-use net::NetPlugin;
+use net::{
+    replay::{Playback, Recorder, ReplayHeader, ReplayPlugin},
+    NetPlugin,
+};
+use pickup::PickupPlugin;
 use player::PlayerPlugin;
 use powerups::PowerupPlugin;
-use ship::ShipPlugin;
+use save::{Save, SavePlugin};
+use scoreboard::ScoreboardPlugin;
+use settings::{Settings, SettingsPlugin};
+use ship::{ShipPlugin, ShipType};
+use simulation::SimulationPlugin;
+use stats::StatsPlugin;
 use ui::UiPlugin;
+use weapon::WeaponPlugin;
 
 #[derive(Debug, Default, Resource)]
 struct Materials {
@@ -32,35 +66,146 @@ struct Materials {
 }
 
 fn main() {
-    let Cli { server: _ } = Cli::parse();
-    App::new()
-        .add_plugins((
-            DefaultPlugins.set(WindowPlugin {
-                primary_window: Some(Window {
-                    fit_canvas_to_parent: true,
-                    prevent_default_event_handling: false,
+    let Cli {
+        server: _,
+        seed,
+        tick_rate,
+        record,
+        replay,
+        ship,
+        name,
+    } = Cli::parse();
+
+    let playback = replay.map(|path| Playback::open(path).expect("failed to open replay"));
+    let (seed, tick_rate) = match &playback {
+        Some(playback) => (playback.header.seed, playback.header.tick_rate),
+        None => (seed.unwrap_or_else(|| fastrand::u64(..)), tick_rate),
+    };
+    // the command line is checked by clap, replays could come from anywhere
+    if let Err(error) = cli::check_tick_rate(tick_rate) {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, format!("the replay's {error}"))
+            .exit();
+    }
+
+    // replays bring their own unlocks and never touch the files on disk
+    let config_dir = save::config_dir().filter(|_| playback.is_none());
+    let save_path = config_dir.as_ref().map(|dir| dir.join("save.ron"));
+    let settings_path = config_dir.as_ref().map(|dir| dir.join("settings.ron"));
+    let save = match &playback {
+        Some(playback) => Save {
+            unlocks: playback.header.unlocks.clone(),
+            ..default()
+        },
+        None => save_path.as_deref().map(save::read).unwrap_or_default(),
+    };
+    let mut settings: Settings = settings_path.as_deref().map(save::read).unwrap_or_default();
+    settings.name_override = name;
+    let main_menu = playback.is_none();
+
+    // a saved pick whose unlock was lost since falls back to the default ship
+    if !save.has_ship(settings.ship) {
+        settings.ship = ShipType::default();
+    }
+    settings.ship_override = match (&playback, ship) {
+        (Some(playback), _) => Some(playback.header.ship),
+        (None, Some(ship)) if save.has_ship(ship) => Some(ship),
+        (None, Some(ship)) => Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!("the {} ship is locked", ship.label()),
+            )
+            .exit(),
+        (None, None) => None,
+    };
+    let ship = settings.ship();
+
+    let mut app = App::new();
+    if playback.is_some() {
+        // replays run headless and as fast as possible, frame timing comes from the recording
+        app.add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
                     ..default()
-                }),
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+        ));
+    } else {
+        app.add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                fit_canvas_to_parent: true,
+                prevent_default_event_handling: false,
                 ..default()
             }),
-            MaterialPlugin::<ShipMaterial>::default(),
-            MaterialPlugin::<SpaceMaterial>::default(),
-            MaterialPlugin::<GridMaterial>::default(),
-            NetPlugin {
-                room: "test".into(),
-            },
-            ShipPlugin,
-            PlayerPlugin,
-            PlayerCameraPlugin,
-            EnemyPlugin,
-            BulletPlugin,
-            PowerupPlugin,
-            UiPlugin,
-        ))
-        .insert_resource(ClearColor(Color::BLACK))
-        .insert_resource(Materials::default())
-        .add_systems(PreStartup, setup)
-        .run();
+            ..default()
+        }));
+    }
+
+    if let Some(playback) = playback {
+        app.insert_resource(playback);
+    }
+
+    if let Some(path) = record {
+        let header = ReplayHeader {
+            seed,
+            tick_rate,
+            unlocks: save.unlocks.clone(),
+            ship,
+        };
+        app.insert_resource(Recorder::create(path, &header).expect("failed to create replay"));
+    }
+
+    app.add_plugins((
+        MaterialPlugin::<ShipMaterial>::default(),
+        MaterialPlugin::<SpaceMaterial>::default(),
+        MaterialPlugin::<GridMaterial>::default(),
+        SimulationPlugin { tick_rate, seed },
+        NetPlugin {
+            room: "test".into(),
+        },
+        ReplayPlugin,
+        SavePlugin {
+            path: save_path,
+            save,
+        },
+        ActionsPlugin,
+        SettingsPlugin {
+            path: settings_path,
+            settings,
+        },
+    ))
+    .add_plugins((
+        ShipPlugin,
+        PlayerPlugin,
+        PlayerCameraPlugin::default(),
+        EnemyPlugin,
+        BulletPlugin,
+        WeaponPlugin,
+        ExperiencePlugin,
+        PickupPlugin,
+        StatsPlugin,
+        BuffPlugin,
+        PowerupPlugin::default(),
+        UiPlugin { main_menu },
+        DamageNumbersPlugin,
+        ScoreboardPlugin {
+            export_dir: config_dir,
+        },
+    ))
+    .insert_resource(ClearColor(Color::BLACK))
+    .insert_resource(Materials::default())
+    .add_systems(PreStartup, setup)
+    .run();
 }
 
 fn setup(
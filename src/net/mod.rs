@@ -1,20 +1,36 @@
 pub mod packet;
+pub mod replay;
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_matchbox::{
     matchbox_socket::{MultipleChannels, PeerId, PeerState, WebRtcSocketBuilder},
     MatchboxSocket,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    constants::{PLAYER_ACCELERATION_RATE, PLAYER_DRAG_COEFFICIENT, PLAYER_MAX_SPEED},
-    ship::{Ship, ShipBundle},
+    buff::Buffs,
+    player::{PlayerName, PrimaryPlayer, TeammateHealth},
+    scoreboard::RunStats,
+    ship::{Muzzles, Ship, ShipBundle, ShipType},
+    simulation::Seed,
     Materials,
 };
 
-use self::packet::{BulletState, Connected, Disconnected, EnemyState, NetworkEvent, PlayerState};
+use self::{
+    packet::{
+        BuffState, BulletState, Connected, Disconnected, EnemyHits, EnemyState, ExperienceGained,
+        GemStates, Handshake, LevelUpState, NameSelected, NetworkEvent, PlayerState,
+        PowerupCollected, PowerupSpawned, PowerupState, RunStatsState, ShipSelected, WaveState,
+    },
+    replay::Recorder,
+};
+
+const MATCHBOX_URL: &str = "wss://bevy-jam-4.fly.dev";
+const UNRELIABLE_CHANNEL: usize = 0;
+const RELIABLE_CHANNEL: usize = 1;
 
-#[derive(Debug, Clone, Eq, PartialEq, Resource)]
+#[derive(Debug, Clone, Eq, PartialEq, Resource, Serialize, Deserialize)]
 pub enum ServerState {
     Host,
     Client,
@@ -32,6 +48,15 @@ pub struct NetData {
     room: String,
 }
 
+/// Sent once the player picks how to play, nothing connects before that
+#[derive(Debug, Clone, Event)]
+pub enum StartGame {
+    /// Joins the default room, or the named one
+    Online { room: Option<String> },
+    /// Hosts a game nobody can join
+    Offline,
+}
+
 #[derive(Debug, Clone)]
 pub struct NetPlugin {
     pub room: String,
@@ -44,41 +69,130 @@ impl Plugin for NetPlugin {
         })
         .insert_resource(ServerState::Unknown)
         .insert_resource(PlayerId(None))
+        .add_event::<StartGame>()
         .add_event::<NetworkEvent>()
         .add_event::<Connected>()
         .add_event::<Disconnected>()
         .add_event::<PlayerState>()
         .add_event::<EnemyState>()
         .add_event::<BulletState>()
-        .add_systems(Startup, startup)
+        .add_event::<Handshake>()
+        .add_event::<GemStates>()
+        .add_event::<ExperienceGained>()
+        .add_event::<LevelUpState>()
+        .add_event::<BuffState>()
+        .add_event::<PowerupSpawned>()
+        .add_event::<PowerupCollected>()
+        .add_event::<ShipSelected>()
+        .add_event::<WaveState>()
+        .add_event::<NameSelected>()
+        .add_event::<RunStatsState>()
+        .add_event::<EnemyHits>()
+        .add_event::<PowerupState>()
+        .add_systems(
+            PreUpdate,
+            read_events.run_if(resource_exists::<MatchboxSocket<MultipleChannels>>()),
+        )
         .add_systems(
             Update,
-            (read_events, connected_handler, disconnected_handler),
+            (
+                start_game,
+                // the ship and name a peer picked come with its connection, so spawn theirs first
+                (
+                    connected_handler,
+                    apply_deferred,
+                    (ship_selected_handler, name_selected_handler),
+                )
+                    .chain(),
+                disconnected_handler,
+            ),
         );
     }
 }
 
-fn startup(mut commands: Commands, _net_data: Res<NetData>) {
-    let room_url = "wss://bevy-jam-4.fly.dev";
-    info!(%room_url, "connecting to matchbox server");
+/// Typed events that incoming [`NetworkEvent`]s are fanned out to
+#[derive(SystemParam)]
+pub struct NetEventWriters<'w> {
+    player_state: EventWriter<'w, PlayerState>,
+    enemy_state: EventWriter<'w, EnemyState>,
+    bullet_state: EventWriter<'w, BulletState>,
+    handshake: EventWriter<'w, Handshake>,
+    gem_states: EventWriter<'w, GemStates>,
+    experience_gained: EventWriter<'w, ExperienceGained>,
+    level_up_state: EventWriter<'w, LevelUpState>,
+    buff_state: EventWriter<'w, BuffState>,
+    powerup_spawned: EventWriter<'w, PowerupSpawned>,
+    powerup_collected: EventWriter<'w, PowerupCollected>,
+    ship_selected: EventWriter<'w, ShipSelected>,
+    wave_state: EventWriter<'w, WaveState>,
+    name_selected: EventWriter<'w, NameSelected>,
+    run_stats_state: EventWriter<'w, RunStatsState>,
+    enemy_hits: EventWriter<'w, EnemyHits>,
+    powerup_state: EventWriter<'w, PowerupState>,
+}
+
+impl NetEventWriters<'_> {
+    pub fn send(&mut self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::PlayerState(state) => self.player_state.send(state),
+            NetworkEvent::EnemyState(state) => self.enemy_state.send(state),
+            NetworkEvent::BulletState(state) => self.bullet_state.send(state),
+            NetworkEvent::Handshake(handshake) => self.handshake.send(handshake),
+            NetworkEvent::GemStates(states) => self.gem_states.send(states),
+            NetworkEvent::ExperienceGained(gained) => self.experience_gained.send(gained),
+            NetworkEvent::LevelUpState(state) => self.level_up_state.send(state),
+            NetworkEvent::BuffState(state) => self.buff_state.send(state),
+            NetworkEvent::PowerupSpawned(spawned) => self.powerup_spawned.send(spawned),
+            NetworkEvent::PowerupCollected(collected) => self.powerup_collected.send(collected),
+            NetworkEvent::ShipSelected(selected) => self.ship_selected.send(selected),
+            NetworkEvent::WaveState(state) => self.wave_state.send(state),
+            NetworkEvent::NameSelected(selected) => self.name_selected.send(selected),
+            NetworkEvent::RunStatsState(state) => self.run_stats_state.send(state),
+            NetworkEvent::EnemyHits(hits) => self.enemy_hits.send(hits),
+            NetworkEvent::PowerupState(state) => self.powerup_state.send(state),
+        }
+    }
+}
+
+fn start_game(
+    mut commands: Commands,
+    mut events: EventReader<StartGame>,
+    mut state: ResMut<ServerState>,
+) {
+    for event in events.read() {
+        match event {
+            StartGame::Online { room } => {
+                let room_url = match room {
+                    Some(room) => format!("{MATCHBOX_URL}/{room}"),
+                    None => MATCHBOX_URL.to_string(),
+                };
+                info!(%room_url, "connecting to matchbox server");
 
-    commands.insert_resource(MatchboxSocket::from(
-        WebRtcSocketBuilder::new(room_url)
-            .add_unreliable_channel()
-            .add_reliable_channel(),
-    ));
+                commands.insert_resource(MatchboxSocket::from(
+                    WebRtcSocketBuilder::new(room_url)
+                        .add_unreliable_channel()
+                        .add_reliable_channel(),
+                ));
+            }
+            StartGame::Offline => {
+                info!("playing offline");
+                *state = ServerState::Host;
+            }
+        }
+    }
 }
 
-fn read_events(
+pub fn read_events(
     mut state: ResMut<ServerState>,
     mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
     mut player_id: ResMut<PlayerId>,
+    seed: Res<Seed>,
+    primary: Query<(&ShipType, &PlayerName), With<PrimaryPlayer>>,
+    mut recorder: Option<ResMut<Recorder>>,
     mut read_events: EventReader<NetworkEvent>,
     mut write_connected: EventWriter<Connected>,
     mut write_disconnected: EventWriter<Disconnected>,
-    mut write_player_state: EventWriter<PlayerState>,
-    mut write_enemy_state: EventWriter<EnemyState>,
-    mut write_bullet_state: EventWriter<BulletState>,
+    mut writers: NetEventWriters,
 ) {
     let peer_updates = socket.update_peers();
 
@@ -98,22 +212,50 @@ fn read_events(
         match peer_state {
             PeerState::Connected => {
                 info!(%peer_id, "connected to peer");
+                // new peers learn the seed from the host and who flies which ship
+                let mut greeting = Vec::new();
+                if *state == ServerState::Host {
+                    greeting.push(NetworkEvent::Handshake(Handshake { seed: seed.0 }));
+                }
+                if let (Some(id), Ok((ship_type, name))) = (player_id.0, primary.get_single()) {
+                    greeting.push(NetworkEvent::ShipSelected(ShipSelected {
+                        id,
+                        ship_type: *ship_type,
+                    }));
+                    greeting.push(NetworkEvent::NameSelected(NameSelected {
+                        id,
+                        name: name.0.clone(),
+                    }));
+                }
+                if !greeting.is_empty() {
+                    socket.get_channel(RELIABLE_CHANNEL).unwrap().send(
+                        packet::net_packet_to_bytes(&packet::NetPacket(greeting)),
+                        peer_id,
+                    );
+                }
+                if let Some(recorder) = &mut recorder {
+                    recorder.record_peer(peer_id, true);
+                }
                 write_connected.send(Connected { peer_id });
             }
             PeerState::Disconnected => {
                 info!(%peer_id, "disconnected from peer");
+                if let Some(recorder) = &mut recorder {
+                    recorder.record_peer(peer_id, false);
+                }
                 write_disconnected.send(Disconnected { peer_id });
             }
         }
     }
 
-    for (peer_id, data) in socket.get_channel(0).unwrap().receive() {
-        if let Some(net_packet) = packet::bytes_to_net_packet(&data) {
-            for packet in net_packet.0 {
-                match packet {
-                    NetworkEvent::PlayerState(state) => write_player_state.send(state),
-                    NetworkEvent::EnemyState(state) => write_enemy_state.send(state),
-                    NetworkEvent::BulletState(state) => write_bullet_state.send(state),
+    for channel in [UNRELIABLE_CHANNEL, RELIABLE_CHANNEL] {
+        for (peer_id, data) in socket.get_channel(channel).unwrap().receive() {
+            if let Some(net_packet) = packet::bytes_to_net_packet(&data) {
+                if let Some(recorder) = &mut recorder {
+                    recorder.record_received(net_packet.clone());
+                }
+                for packet in net_packet.0 {
+                    writers.send(packet);
                 }
             }
         }
@@ -121,13 +263,27 @@ fn read_events(
 
     let events = read_events.read().cloned().collect::<Vec<_>>();
     if !events.is_empty() {
-        let net_packet = packet::net_packet_to_bytes(&packet::NetPacket(events));
+        if let Some(recorder) = &mut recorder {
+            recorder.record_sent(packet::NetPacket(events.clone()));
+        }
+
+        let (reliable, unreliable): (Vec<_>, Vec<_>) =
+            events.into_iter().partition(NetworkEvent::reliable);
         let peers = socket.connected_peers().collect::<Vec<_>>();
-        for peer_id in peers {
-            socket
-                .get_channel(0)
-                .unwrap()
-                .send(net_packet.clone(), peer_id.clone());
+        for (channel, events) in [
+            (RELIABLE_CHANNEL, reliable),
+            (UNRELIABLE_CHANNEL, unreliable),
+        ] {
+            if events.is_empty() {
+                continue;
+            }
+            let net_packet = packet::net_packet_to_bytes(&packet::NetPacket(events));
+            for peer_id in &peers {
+                socket
+                    .get_channel(channel)
+                    .unwrap()
+                    .send(net_packet.clone(), *peer_id);
+            }
         }
     }
 }
@@ -139,24 +295,60 @@ fn connected_handler(
     server: Res<AssetServer>,
 ) {
     for event in reader.read() {
+        // flies the default ship until the peer says which one it picked
+        let ship_type = ShipType::default();
         commands.spawn((
-            ShipBundle {
-                ship: Ship::new(
-                    PLAYER_MAX_SPEED,
-                    PLAYER_ACCELERATION_RATE,
-                    PLAYER_DRAG_COEFFICIENT,
-                ),
-                material_mesh: MaterialMeshBundle {
-                    mesh: server.load("player2.glb#Mesh0/Primitive0"),
-                    material: materials.ship_material.clone().unwrap(),
-                    ..Default::default()
-                },
-            },
+            ShipBundle::player(ship_type, &server, materials.ship_material.clone().unwrap()),
+            ship_type,
             PlayerPeerId(event.peer_id),
+            PlayerName::peer(event.peer_id),
+            TeammateHealth::default(),
+            RunStats::default(),
+            Buffs::default(),
         ));
     }
 }
 
+/// Swaps remote ships to the model and handling their players picked
+fn ship_selected_handler(
+    mut reader: EventReader<ShipSelected>,
+    server: Res<AssetServer>,
+    mut peer_ships: Query<(
+        &mut Ship,
+        &mut ShipType,
+        &mut Handle<Mesh>,
+        &mut Muzzles,
+        &PlayerPeerId,
+    )>,
+) {
+    for event in reader.read() {
+        for (mut ship, mut ship_type, mut mesh, mut muzzles, PlayerPeerId(id)) in &mut peer_ships {
+            if id != &event.id || *ship_type == event.ship_type {
+                continue;
+            }
+            let spec = event.ship_type.spec();
+            *ship = spec.ship();
+            *ship_type = event.ship_type;
+            *mesh = spec.mesh(&server);
+            *muzzles = Muzzles::new(server.load(spec.model));
+        }
+    }
+}
+
+fn name_selected_handler(
+    mut reader: EventReader<NameSelected>,
+    mut peers: Query<(&mut PlayerName, &PlayerPeerId)>,
+) {
+    for event in reader.read() {
+        for (mut name, PlayerPeerId(id)) in &mut peers {
+            if id == &event.id {
+                // names from peers go through the same limits as our own
+                name.set_if_neq(PlayerName::new(&event.name));
+            }
+        }
+    }
+}
+
 fn disconnected_handler(
     mut commands: Commands,
     mut reader: EventReader<Disconnected>,
@@ -168,7 +360,7 @@ fn disconnected_handler(
             .iter_mut()
             .find(|(_, _, _, PlayerPeerId(id))| id == &event.peer_id)
         {
-            commands.entity(entity).despawn();
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
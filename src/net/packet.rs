@@ -7,6 +7,8 @@ use bevy::{
 use bevy_matchbox::matchbox_socket::PeerId;
 use serde::{Deserialize, Serialize};
 
+use crate::{buff::ActiveBuff, powerups::PowerupType, scoreboard::RunStats, ship::ShipType};
+
 const MAX_UNCOMPRESSED_SIZE: usize = 256;
 
 #[derive(Debug, Event)]
@@ -24,6 +26,8 @@ pub struct PlayerState {
     pub id: PeerId,
     pub position: Vec3,
     pub rotation: Quat,
+    pub health: f32,
+    pub max_health: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Event)]
@@ -31,6 +35,9 @@ pub struct EnemyState {
     pub id: u16,
     pub position: Vec2,
     pub visible: bool,
+    pub boss: bool,
+    /// Bumped every time the slot is reused, so hits on the last enemy there don't carry over
+    pub generation: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Event)]
@@ -41,11 +48,148 @@ pub struct BulletState {
     pub visible: bool,
 }
 
+/// Damage a client's weapons did to one enemy, summed over the enemy's life so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnemyHit {
+    pub id: u16,
+    pub generation: u16,
+    pub total: f32,
+}
+
+/// Sent by clients every tick they have hit something, the host applies whatever part of the
+/// totals is new and decides who dies. Totals make a dropped packet cost nothing
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct EnemyHits {
+    pub id: PeerId,
+    pub hits: Vec<EnemyHit>,
+}
+
+/// Sent by the host every tick so clients show the same wave and kill count
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct WaveState {
+    pub wave: u32,
+    pub kills: u32,
+}
+
+/// Sent by the host to a newly connected peer on the reliable channel
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct Handshake {
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GemState {
+    pub id: u16,
+    pub position: Vec2,
+}
+
+/// Sent by the host every tick, gems left out are hidden
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct GemStates {
+    pub gems: Vec<GemState>,
+}
+
+/// The host picked up a gem on behalf of a remote player
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct ExperienceGained {
+    pub id: PeerId,
+    pub experience: f32,
+}
+
+/// Buffs of a player, sent whenever one starts or ends
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct BuffState {
+    pub id: PeerId,
+    pub buffs: Vec<ActiveBuff>,
+}
+
+/// The host took a powerup from the pool
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct PowerupSpawned {
+    pub id: u16,
+    pub powerup_type: PowerupType,
+    pub position: Vec3,
+    /// Seconds left, players joining late are sent the powerups already on the map
+    pub ttl: f32,
+}
+
+/// Sent by the host every tick for each powerup on the map, it's the one pulling them in
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct PowerupState {
+    pub id: u16,
+    pub position: Vec2,
+}
+
+/// The host decided who picked up a powerup
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct PowerupCollected {
+    pub id: u16,
+    pub collector: PeerId,
+}
+
+/// A player started or finished choosing an upgrade, the game stays paused until all are done
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct LevelUpState {
+    pub id: PeerId,
+    pub choosing: bool,
+}
+
+/// Sent by every player to a newly connected peer so it spawns the right ship for them
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct ShipSelected {
+    pub id: PeerId,
+    pub ship_type: ShipType,
+}
+
+/// Sent by every player about their own ship once a second, for the scoreboard
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct RunStatsState {
+    pub id: PeerId,
+    pub stats: RunStats,
+}
+
+/// Sent along with [`ShipSelected`] when a peer connects, and again whenever the name changes
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct NameSelected {
+    pub id: PeerId,
+    pub name: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Event)]
 pub enum NetworkEvent {
     PlayerState(PlayerState),
     EnemyState(EnemyState),
     BulletState(BulletState),
+    Handshake(Handshake),
+    GemStates(GemStates),
+    ExperienceGained(ExperienceGained),
+    LevelUpState(LevelUpState),
+    BuffState(BuffState),
+    PowerupSpawned(PowerupSpawned),
+    PowerupCollected(PowerupCollected),
+    ShipSelected(ShipSelected),
+    WaveState(WaveState),
+    NameSelected(NameSelected),
+    RunStatsState(RunStatsState),
+    EnemyHits(EnemyHits),
+    PowerupState(PowerupState),
+}
+
+impl NetworkEvent {
+    /// One-off events that must arrive, state that is resent every tick can be dropped
+    pub fn reliable(&self) -> bool {
+        matches!(
+            self,
+            NetworkEvent::Handshake(_)
+                | NetworkEvent::ExperienceGained(_)
+                | NetworkEvent::LevelUpState(_)
+                | NetworkEvent::BuffState(_)
+                | NetworkEvent::PowerupSpawned(_)
+                | NetworkEvent::PowerupCollected(_)
+                | NetworkEvent::ShipSelected(_)
+                | NetworkEvent::NameSelected(_)
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
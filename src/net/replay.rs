@@ -0,0 +1,277 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write as _},
+    path::Path,
+    time::Duration,
+};
+
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    time::{TimeSystem, TimeUpdateStrategy},
+};
+use bevy_matchbox::matchbox_socket::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    player::{Player, PlayerInput},
+    save::Unlock,
+    ship::ShipType,
+    simulation::{PauseReason, Paused},
+};
+
+use super::{
+    packet::{self, Connected, Disconnected, NetPacket, NetworkEvent},
+    NetEventWriters, PlayerId, PlayerPeerId, ServerState,
+};
+
+/// Records sessions to disk and plays them back. Recording is enabled by inserting a [`Recorder`],
+/// playback by inserting a [`Playback`], both before the app runs
+#[derive(Debug)]
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            First,
+            playback
+                .before(TimeSystem)
+                .run_if(resource_exists::<Playback>()),
+        )
+        .add_systems(PreUpdate, verify.run_if(resource_exists::<Playback>()))
+        .add_systems(Last, record.run_if(resource_exists::<Recorder>()));
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub seed: u64,
+    pub tick_rate: f64,
+    /// Unlocks change the starting loadout and level up choices, so they're part of the run
+    pub unlocks: Vec<Unlock>,
+    pub ship: ShipType,
+}
+
+/// Everything that entered or left the simulation during one frame
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub delta: Duration,
+    pub state: Option<ServerState>,
+    pub player_id: Option<PeerId>,
+    /// Peers that connected (`true`) or disconnected (`false`)
+    pub peers: Vec<(PeerId, bool)>,
+    pub received: Vec<NetPacket>,
+    pub sent: Vec<NetPacket>,
+    pub input: PlayerInput,
+    /// A menu held the game still, menus themselves don't run during playback
+    pub menu_paused: bool,
+}
+
+#[derive(Resource)]
+pub struct Recorder {
+    encoder: flate2::write::ZlibEncoder<BufWriter<File>>,
+    frame: ReplayFrame,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>, header: &ReplayHeader) -> bincode::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = flate2::write::ZlibEncoder::new(file, flate2::Compression::default());
+        bincode::serialize_into(&mut encoder, header)?;
+
+        Ok(Self {
+            encoder,
+            frame: ReplayFrame::default(),
+        })
+    }
+
+    pub fn record_peer(&mut self, peer_id: PeerId, connected: bool) {
+        self.frame.peers.push((peer_id, connected));
+    }
+
+    pub fn record_received(&mut self, packet: NetPacket) {
+        self.frame.received.push(packet);
+    }
+
+    pub fn record_sent(&mut self, packet: NetPacket) {
+        self.frame.sent.push(packet);
+    }
+}
+
+#[derive(Resource)]
+pub struct Playback {
+    pub header: ReplayHeader,
+    decoder: flate2::read::ZlibDecoder<BufReader<File>>,
+    frame: Option<ReplayFrame>,
+    index: usize,
+    diverged: bool,
+}
+
+impl Playback {
+    pub fn open(path: impl AsRef<Path>) -> bincode::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        let mut decoder = flate2::read::ZlibDecoder::new(file);
+        let header = bincode::deserialize_from(&mut decoder)?;
+
+        Ok(Self {
+            header,
+            decoder,
+            frame: None,
+            index: 0,
+            diverged: false,
+        })
+    }
+
+    /// The recording is flushed frame by frame, so a truncated stream is simply the end of it
+    fn next_frame(&mut self) -> Option<ReplayFrame> {
+        bincode::deserialize_from(&mut self.decoder).ok()
+    }
+}
+
+fn record(
+    time: Res<Time<Real>>,
+    state: Res<ServerState>,
+    player_id: Res<PlayerId>,
+    paused: Res<Paused>,
+    mut recorder: ResMut<Recorder>,
+    input: Query<&PlayerInput, (With<Player>, Without<PlayerPeerId>)>,
+    mut exit: EventReader<AppExit>,
+) {
+    let mut frame = std::mem::take(&mut recorder.frame);
+    frame.delta = time.delta();
+    frame.state = Some(state.clone());
+    frame.player_id = player_id.0;
+    frame.input = input.get_single().cloned().unwrap_or_default();
+    frame.menu_paused = paused.contains(PauseReason::Menu);
+
+    if let Err(error) = bincode::serialize_into(&mut recorder.encoder, &frame) {
+        error!(%error, "failed to write replay frame");
+    }
+
+    // the window may close without dropping the world, so flush every frame
+    if let Err(error) = recorder.encoder.flush() {
+        error!(%error, "failed to flush replay");
+    }
+
+    if exit.read().next().is_some() {
+        if let Err(error) = recorder.encoder.try_finish() {
+            error!(%error, "failed to finish replay");
+        }
+    }
+}
+
+fn playback(
+    mut playback: ResMut<Playback>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut state: ResMut<ServerState>,
+    mut player_id: ResMut<PlayerId>,
+    mut paused: ResMut<Paused>,
+    mut input: Query<&mut PlayerInput, (With<Player>, Without<PlayerPeerId>)>,
+    mut write_connected: EventWriter<Connected>,
+    mut write_disconnected: EventWriter<Disconnected>,
+    mut writers: NetEventWriters,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(frame) = playback.next_frame() else {
+        info!(frames = playback.index, "replay finished");
+        playback.frame = None;
+        exit.send(AppExit);
+        return;
+    };
+    playback.index += 1;
+
+    *time_strategy = TimeUpdateStrategy::ManualDuration(frame.delta);
+    if let Some(frame_state) = &frame.state {
+        *state = frame_state.clone();
+    }
+    *player_id = PlayerId(frame.player_id);
+    paused.set(PauseReason::Menu, frame.menu_paused);
+
+    if let Ok(mut input) = input.get_single_mut() {
+        *input = frame.input.clone();
+    }
+
+    for (peer_id, connected) in &frame.peers {
+        if *connected {
+            write_connected.send(Connected { peer_id: *peer_id });
+        } else {
+            write_disconnected.send(Disconnected { peer_id: *peer_id });
+        }
+    }
+
+    for net_packet in &frame.received {
+        for event in &net_packet.0 {
+            writers.send(event.clone());
+        }
+    }
+
+    playback.frame = Some(frame);
+}
+
+/// Compares what the simulation sends against the recording, the first mismatch is where the
+/// replayed session diverged
+fn verify(mut playback: ResMut<Playback>, mut read_events: EventReader<NetworkEvent>) {
+    let Some(frame) = &playback.frame else {
+        return;
+    };
+
+    let events = read_events.read().cloned().collect::<Vec<_>>();
+    let recorded = frame
+        .sent
+        .iter()
+        .map(packet::net_packet_to_bytes)
+        .collect::<Vec<_>>();
+    let replayed = if events.is_empty() {
+        Vec::new()
+    } else {
+        vec![packet::net_packet_to_bytes(&NetPacket(events))]
+    };
+
+    if recorded != replayed && !playback.diverged {
+        warn!(frame = playback.index, "replay diverged from recording");
+        playback.diverged = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::{Playback, Recorder, ReplayFrame, ReplayHeader};
+    use crate::ship::ShipType;
+
+    #[test]
+    fn test_unfinished_replay_round_trip() {
+        // the pid keeps concurrent test runs from sharing the file
+        let path =
+            std::env::temp_dir().join(format!("bevy-jam-4-replay-test-{}.bin", std::process::id()));
+        let header = ReplayHeader {
+            seed: 7,
+            tick_rate: 60.0,
+            unlocks: Vec::new(),
+            ship: ShipType::Fighter,
+        };
+
+        // write and flush without finishing, as happens when the window is closed
+        let mut recorder = Recorder::create(&path, &header).unwrap();
+        for i in 0..3 {
+            let frame = ReplayFrame {
+                delta: std::time::Duration::from_millis(i),
+                ..Default::default()
+            };
+            bincode::serialize_into(&mut recorder.encoder, &frame).unwrap();
+            recorder.encoder.flush().unwrap();
+        }
+        std::mem::forget(recorder);
+
+        let mut playback = Playback::open(&path).unwrap();
+        assert_eq!(playback.header.seed, 7);
+        for i in 0..3 {
+            let frame = playback.next_frame().unwrap();
+            assert_eq!(frame.delta, std::time::Duration::from_millis(i));
+        }
+        assert!(playback.next_frame().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+
+use crate::{
+    constants::{PICKUP_ACCELERATION, PLAYER_PICKUP_RADIUS},
+    net::{PlayerPeerId, ServerState},
+    player::Player,
+    simulation::SimulationSet,
+    stats::{Stat, Stats},
+    util::Vec3Extension,
+};
+
+/// Pulls gems and powerups towards players that come close enough
+#[derive(Debug)]
+pub struct PickupPlugin;
+
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, attract.in_set(SimulationSet::Update));
+    }
+}
+
+/// Something players collect by touching it
+#[derive(Component, Clone, Default)]
+pub struct Pickup {
+    /// The player it's flying towards, once attracted it follows them until collected
+    pub target: Option<Entity>,
+    speed: f32,
+}
+
+impl Pickup {
+    /// Flies towards the player however far away they are
+    pub fn attract(&mut self, target: Entity) {
+        if self.target.is_none() {
+            self.target = Some(target);
+        }
+    }
+
+    /// Forgets the target, use when taking the pickup from a pool
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.speed = 0.0;
+    }
+}
+
+/// Distance at which pickups start flying towards a player. Upgrades of remote players aren't
+/// sent, so the host pulls pickups towards them from the base radius
+fn pickup_radius(stats: Option<&Stats>) -> f32 {
+    stats.map_or(PLAYER_PICKUP_RADIUS, |stats| stats.get(Stat::PickupRadius))
+}
+
+pub fn attract(
+    time: Res<Time>,
+    status: Res<ServerState>,
+    mut pickups: Query<
+        (&mut Transform, &mut Pickup, &Visibility),
+        (Without<Player>, Without<PlayerPeerId>),
+    >,
+    players: Query<(Entity, &Transform, Option<&Stats>), Or<(With<Player>, With<PlayerPeerId>)>>,
+) {
+    // clients are sent pickup positions by the host
+    if *status == ServerState::Client {
+        return;
+    }
+
+    let dt = time.delta_seconds();
+    for (mut transform, mut pickup, visibility) in &mut pickups {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        let position = transform.translation.xz();
+        if pickup.target.is_none() {
+            pickup.target = players
+                .iter()
+                .map(|(entity, player_transform, stats)| {
+                    (
+                        entity,
+                        player_transform.translation.xz().distance(position),
+                        pickup_radius(stats),
+                    )
+                })
+                .filter(|(_, distance, radius)| distance < radius)
+                .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+                .map(|(entity, _, _)| entity);
+        }
+
+        let Some(target) = pickup.target else {
+            continue;
+        };
+        // the player left the game
+        let Ok((_, target_transform, _)) = players.get(target) else {
+            pickup.reset();
+            continue;
+        };
+
+        pickup.speed += PICKUP_ACCELERATION * dt;
+        let destination = Vec3::new(
+            target_transform.translation.x,
+            transform.translation.y,
+            target_transform.translation.z,
+        );
+        transform.translation = transform
+            .translation
+            .move_towards(destination, pickup.speed * dt);
+    }
+}
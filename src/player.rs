@@ -1,105 +1,485 @@
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::prelude::*;
+use bevy_matchbox::matchbox_socket::PeerId;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    constants::{PLAYER_ACCELERATION_RATE, PLAYER_DRAG_COEFFICIENT, PLAYER_MAX_SPEED},
+    buff::Buffs,
+    camera::{window_to_world, CameraController},
+    constants::{EXPERIENCE_PER_LEVEL, PLAYER_MAX_HEALTH, PLAYER_PICKUP_RADIUS},
+    enemy::Enemy,
+    input::{read_actions, Action, ActionState, Aim},
     materials::{GridMaterial, ShipMaterial, SpaceMaterial},
-    net::{packet::PlayerState, PlayerId, PlayerPeerId},
-    ship::{Ship, ShipBundle},
+    net::{
+        packet::{NameSelected, NetworkEvent, PlayerState, ShipSelected},
+        replay::Playback,
+        PlayerId, PlayerPeerId,
+    },
+    save::Save,
+    scoreboard::RunStats,
+    settings::Settings,
+    ship::{Ship, ShipBundle, ShipType},
+    stats::{Stat, StatModifier, Stats},
+    ui::MenuState,
+    weapon::Weapons,
     Materials,
 };
 
+/// Longer names are cut off, they have to fit on nameplates
+pub const MAX_NAME_LENGTH: usize = 16;
+/// Couch co-op players, the split screen has room for this many views
+pub const MAX_LOCAL_PLAYERS: usize = 4;
+/// How far from the first local ship players joining on the same machine appear
+const JOIN_OFFSET: f32 = 3.0;
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, startup)
-            .add_systems(PreUpdate, update);
+        app.add_event::<AddLocalPlayer>()
+            .add_systems(Startup, startup)
+            .add_systems(
+                PreUpdate,
+                (
+                    read_input
+                        .after(read_actions)
+                        .run_if(not(resource_exists::<Playback>()))
+                        .run_if(in_state(MenuState::Closed)),
+                    update.after(read_input),
+                ),
+            )
+            .add_systems(
+                Update,
+                (rename, change_fire_mode, change_ship, add_local_player),
+            )
+            .add_systems(OnExit(MenuState::Closed), clear_input);
     }
 }
 
 #[derive(Bundle)]
 pub struct PlayerBundle {
     pub player: Player,
+    pub input: PlayerInput,
+    pub fire_mode: FireMode,
+    pub stats: Stats,
+    pub buffs: Buffs,
+    pub weapons: Weapons,
+    pub ship_type: ShipType,
+    pub name: PlayerName,
+    pub run_stats: RunStats,
+    pub local: LocalPlayer,
+    pub actions: ActionState,
     pub ship: ShipBundle,
 }
 
+impl PlayerBundle {
+    /// Starts with the ship's stats and the upgrades unlocked in the save
+    fn new(
+        ship_type: ShipType,
+        save: &Save,
+        server: &AssetServer,
+        materials: &Materials,
+        name: PlayerName,
+        local: LocalPlayer,
+    ) -> Self {
+        let spec = ship_type.spec();
+        let mut stats = Stats::new([
+            (Stat::MaxHealth, spec.health),
+            (Stat::MaxSpeed, spec.max_speed),
+            (Stat::Acceleration, spec.acceleration),
+            (Stat::Damage, 1.0),
+            (Stat::FireRate, 1.0),
+            (Stat::PickupRadius, PLAYER_PICKUP_RADIUS),
+        ]);
+        for stat in save.starting_upgrades() {
+            stats.add(StatModifier::permanent(stat, stat.upgrade()));
+        }
+
+        let mut player = Player::new();
+        player.health = stats.get(Stat::MaxHealth);
+
+        Self {
+            player,
+            input: PlayerInput::default(),
+            fire_mode: FireMode::default(),
+            stats,
+            buffs: Buffs::default(),
+            weapons: Weapons::new(spec.weapon),
+            ship_type,
+            name,
+            run_stats: RunStats::default(),
+            local,
+            actions: ActionState::default(),
+            ship: ShipBundle::player(ship_type, server, materials.ship_material.clone().unwrap()),
+        }
+    }
+}
+
+/// A player on this machine, there can be several for couch co-op
+#[derive(Component, Debug, Clone, Copy)]
+pub struct LocalPlayer {
+    /// 0 for the first player, picks the camera in split-screen
+    pub seat: usize,
+    /// The gamepad the player joined with, `None` plays with the keyboard, mouse, touch and any
+    /// gamepad nobody else took
+    pub gamepad: Option<Gamepad>,
+}
+
+/// The first local player, the one the HUD shows and the network and replays know about
+#[derive(Component, Debug)]
+pub struct PrimaryPlayer;
+
+/// Spawns another local player controlled by the gamepad
+#[derive(Event, Debug)]
+pub struct AddLocalPlayer {
+    pub gamepad: Gamepad,
+}
+
+/// Shown on nameplates, in the event feed and on the scoreboard
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerName(pub String);
+
+impl PlayerName {
+    /// Trimmed and cut to [`MAX_NAME_LENGTH`], a blank name becomes the default one
+    pub fn new(name: &str) -> Self {
+        let name = name
+            .trim()
+            .chars()
+            .take(MAX_NAME_LENGTH)
+            .collect::<String>();
+        if name.is_empty() {
+            Self(Settings::default().name)
+        } else {
+            Self(name)
+        }
+    }
+
+    /// Until the peer sends its name, short enough to tell players apart
+    pub fn peer(peer_id: PeerId) -> Self {
+        let mut name = peer_id.0.to_string();
+        name.truncate(8);
+        Self(name)
+    }
+}
+
+/// Health of a remote player, replicated along with their position
+#[derive(Component, Debug, Default)]
+pub struct TeammateHealth {
+    pub health: f32,
+    pub max_health: f32,
+}
+
 #[derive(Component)]
 pub struct Player {
     pub health: f32,
-    pub damage: f32,
-    pub gun: u32,
+    /// Seconds remaining before contact damage can be taken again
+    pub invulnerability: f32,
+    /// Experience collected towards the next level
+    pub experience: f32,
+    pub level: u32,
+}
+
+/// What the local player wants to do this frame, read from devices or a replay
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub move_dir: Vec3,
+    pub look_dir: Vec3,
+    pub fire: bool,
+    /// Index of the level up choice picked this frame
+    pub upgrade: Option<usize>,
+}
+
+/// How the fire input turns into shots, the first player's is kept in the settings
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FireMode {
+    /// Fire while the button is held
+    #[default]
+    Hold,
+    /// Each press starts or stops firing
+    Toggle,
+    /// Always firing, aim with the mouse
+    Auto,
+    /// Always firing at the nearest enemy
+    AutoTarget,
+}
+
+impl FireMode {
+    pub fn next(self) -> Self {
+        match self {
+            FireMode::Hold => FireMode::Toggle,
+            FireMode::Toggle => FireMode::Auto,
+            FireMode::Auto => FireMode::AutoTarget,
+            FireMode::AutoTarget => FireMode::Hold,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FireMode::Hold => "Hold",
+            FireMode::Toggle => "Toggle",
+            FireMode::Auto => "Auto",
+            FireMode::AutoTarget => "Auto-target",
+        }
+    }
 }
 
 impl Player {
     pub fn new() -> Self {
         Self {
-            health: 100.0,
-            damage: 1.0,
-            gun: 0,
+            health: PLAYER_MAX_HEALTH,
+            invulnerability: 0.0,
+            experience: 0.0,
+            level: 1,
         }
     }
+
+    pub fn experience_to_level_up(&self) -> f32 {
+        EXPERIENCE_PER_LEVEL * self.level as f32
+    }
 }
 
-fn startup(mut commands: Commands, server: Res<AssetServer>, materials: Res<Materials>) {
-    commands.spawn(PlayerBundle {
-        player: Player::new(),
-        ship: ShipBundle {
-            ship: Ship::new(
-                PLAYER_MAX_SPEED,
-                PLAYER_ACCELERATION_RATE,
-                PLAYER_DRAG_COEFFICIENT,
-            ),
-            material_mesh: MaterialMeshBundle {
-                mesh: server.load("player2.glb#Mesh0/Primitive0"),
-                material: materials.ship_material.clone().unwrap(),
-                ..Default::default()
-            },
+fn startup(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+    materials: Res<Materials>,
+    save: Res<Save>,
+    settings: Res<Settings>,
+) {
+    let mut bundle = PlayerBundle::new(
+        settings.ship(),
+        &save,
+        &server,
+        &materials,
+        PlayerName::new(settings.name()),
+        LocalPlayer {
+            seat: 0,
+            gamepad: None,
         },
-    });
+    );
+    bundle.fire_mode = settings.fire_mode;
+    commands.spawn((bundle, PrimaryPlayer));
 }
 
-fn update(
-    keys: Res<Input<KeyCode>>,
+/// Couch co-op players fly the same ship as the first one and appear next to it
+fn add_local_player(
+    mut commands: Commands,
+    mut events: EventReader<AddLocalPlayer>,
+    server: Res<AssetServer>,
     materials: Res<Materials>,
-    mut ship_materials: ResMut<Assets<ShipMaterial>>,
-    mut space_materials: ResMut<Assets<SpaceMaterial>>,
-    mut line_materials: ResMut<Assets<GridMaterial>>,
-    mut ship: Query<(&mut Ship, &Transform), (With<Player>, Without<PlayerPeerId>)>,
-    window: Query<&Window, With<PrimaryWindow>>,
-    camera: Query<(&Camera, &GlobalTransform)>,
-    mut player_state_writer: EventWriter<PlayerState>,
-    player_id: Res<PlayerId>,
+    save: Res<Save>,
+    settings: Res<Settings>,
+    players: Query<(&LocalPlayer, &Transform)>,
 ) {
-    let window = window.single();
-    let (camera, global_transform) = camera.single();
-    let (mut ship, transform) = ship.single_mut();
+    let mut seat = players.iter().count();
+    let origin = players
+        .iter()
+        .find(|(local, _)| local.seat == 0)
+        .map_or(Vec3::ZERO, |(_, transform)| transform.translation);
 
-    ship.move_dir = Vec3::ZERO;
-    if keys.any_pressed([KeyCode::Up, KeyCode::W]) {
-        ship.move_dir.z -= 1.0;
+    for event in events.read() {
+        let taken = players
+            .iter()
+            .any(|(local, _)| local.gamepad == Some(event.gamepad));
+        if taken || seat >= MAX_LOCAL_PLAYERS {
+            continue;
+        }
+        let mut bundle = PlayerBundle::new(
+            settings.ship(),
+            &save,
+            &server,
+            &materials,
+            PlayerName::new(&format!("Player {}", seat + 1)),
+            LocalPlayer {
+                seat,
+                gamepad: Some(event.gamepad),
+            },
+        );
+        let transform = Transform::from_translation(origin + Vec3::X * JOIN_OFFSET * seat as f32);
+        bundle.ship.material_mesh.transform = transform;
+        bundle.ship.interpolated.snap(&transform);
+        bundle.fire_mode = settings.fire_mode;
+        commands.spawn(bundle);
+        info!(seat, gamepad = event.gamepad.id, "local player joined");
+        seat += 1;
     }
-    if keys.any_pressed([KeyCode::Down, KeyCode::S]) {
-        ship.move_dir.z += 1.0;
+}
+
+/// Stops the ship and its guns while a menu is open
+fn clear_input(mut players: Query<&mut PlayerInput, (With<Player>, Without<PlayerPeerId>)>) {
+    for mut input in &mut players {
+        *input = PlayerInput::default();
     }
-    if keys.any_pressed([KeyCode::Left, KeyCode::A]) {
-        ship.move_dir.x -= 1.0;
+}
+
+/// Renaming in the settings shows up for everyone right away
+fn rename(
+    settings: Res<Settings>,
+    player_id: Res<PlayerId>,
+    mut players: Query<&mut PlayerName, With<PrimaryPlayer>>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    if !settings.is_changed() {
+        return;
     }
-    if keys.any_pressed([KeyCode::Right, KeyCode::D]) {
-        ship.move_dir.x += 1.0;
+    let name = PlayerName::new(settings.name());
+    for mut player_name in &mut players {
+        if *player_name == name {
+            continue;
+        }
+        *player_name = name.clone();
+        if let Some(id) = player_id.0 {
+            net_event_writer.send(NetworkEvent::NameSelected(NameSelected {
+                id,
+                name: name.0.clone(),
+            }));
+        }
     }
+}
 
-    let plane_origin = Vec3::new(0.0, 0.0, 0.0);
-    let plane_normal = Vec3::new(0.0, 1.0, 0.0);
-    let Some(viewport_position) = window.cursor_position() else {
-        return;
-    };
-    let Some(ray) = camera.viewport_to_world(global_transform, viewport_position) else {
+/// A ship picked in the menu replaces the first player's, along with everything it starts with.
+/// The menu only offers it before the game starts
+fn change_ship(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+    materials: Res<Materials>,
+    save: Res<Save>,
+    settings: Res<Settings>,
+    player_id: Res<PlayerId>,
+    players: Query<(Entity, &ShipType, &PlayerName), With<PrimaryPlayer>>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    if !settings.is_changed() {
         return;
-    };
-    let Some(distance) = ray.intersect_plane(plane_origin, plane_normal) else {
+    }
+    let ship_type = settings.ship();
+    for (entity, current, name) in &players {
+        if *current == ship_type {
+            continue;
+        }
+        let mut bundle = PlayerBundle::new(
+            ship_type,
+            &save,
+            &server,
+            &materials,
+            name.clone(),
+            LocalPlayer {
+                seat: 0,
+                gamepad: None,
+            },
+        );
+        bundle.fire_mode = settings.fire_mode;
+        commands.entity(entity).insert(bundle);
+        if let Some(id) = player_id.0 {
+            net_event_writer.send(NetworkEvent::ShipSelected(ShipSelected { id, ship_type }));
+        }
+    }
+}
+
+/// The first player's fire mode picked in the settings
+fn change_fire_mode(
+    settings: Res<Settings>,
+    mut players: Query<&mut FireMode, With<PrimaryPlayer>>,
+) {
+    if !settings.is_changed() {
         return;
-    };
-    ship.look_dir = ray.get_point(distance) - transform.translation;
+    }
+    for mut fire_mode in &mut players {
+        fire_mode.set_if_neq(settings.fire_mode);
+    }
+}
+
+pub fn read_input(
+    mut settings: ResMut<Settings>,
+    mut players: Query<
+        (
+            &mut PlayerInput,
+            &mut FireMode,
+            &Transform,
+            &ActionState,
+            &LocalPlayer,
+            Has<PrimaryPlayer>,
+        ),
+        With<Player>,
+    >,
+    enemies: Query<(&Transform, &Visibility), With<Enemy>>,
+    cameras: Query<(&Camera, &GlobalTransform, &CameraController)>,
+) {
+    for (mut input, mut fire_mode, transform, actions, local, primary) in &mut players {
+        input.move_dir = Vec3::new(actions.move_dir.x, 0.0, actions.move_dir.y);
+
+        if actions.just_pressed(Action::FireMode) {
+            *fire_mode = fire_mode.next();
+            info!(fire_mode = fire_mode.label(), "fire mode changed");
+            // kept for the next run
+            if primary {
+                settings.fire_mode = *fire_mode;
+            }
+        }
+
+        input.fire = match *fire_mode {
+            FireMode::Hold => actions.pressed(Action::Fire),
+            FireMode::Toggle => input.fire != actions.just_pressed(Action::Fire),
+            FireMode::Auto | FireMode::AutoTarget => true,
+        };
+
+        input.upgrade = actions.upgrade;
+
+        if *fire_mode == FireMode::AutoTarget {
+            let nearest = enemies
+                .iter()
+                .filter(|(_, visibility)| **visibility != Visibility::Hidden)
+                .map(|(enemy_transform, _)| enemy_transform.translation - transform.translation)
+                .min_by(|a, b| a.length_squared().total_cmp(&b.length_squared()));
+
+            // with nothing to shoot at, fall back to aiming
+            if let Some(to_enemy) = nearest {
+                input.look_dir = to_enemy;
+                continue;
+            }
+        }
+
+        match actions.aim {
+            Aim::None => {}
+            Aim::Direction(direction) => {
+                input.look_dir = Vec3::new(direction.x, 0.0, direction.y);
+            }
+            Aim::Cursor(position) => {
+                // through the camera following this player, or the shared one which is the first
+                // seat's
+                let camera = cameras
+                    .iter()
+                    .find(|(_, _, controller)| controller.seat == local.seat)
+                    .or_else(|| {
+                        cameras
+                            .iter()
+                            .find(|(_, _, controller)| controller.seat == 0)
+                    });
+                if let Some(point) = camera.and_then(|(camera, camera_transform, _)| {
+                    window_to_world(camera, camera_transform, position)
+                }) {
+                    input.look_dir = point - transform.translation;
+                }
+            }
+        }
+    }
+}
+
+fn update(
+    materials: Res<Materials>,
+    mut ship_materials: ResMut<Assets<ShipMaterial>>,
+    mut space_materials: ResMut<Assets<SpaceMaterial>>,
+    mut line_materials: ResMut<Assets<GridMaterial>>,
+    mut ships: Query<(&mut Ship, &PlayerInput), (With<Player>, Without<PlayerPeerId>)>,
+    primary: Query<&Transform, With<PrimaryPlayer>>,
+    mut player_state_writer: EventWriter<PlayerState>,
+    player_id: Res<PlayerId>,
+) {
+    for (mut ship, input) in &mut ships {
+        ship.move_dir = input.move_dir;
+        ship.look_dir = input.look_dir;
+    }
+
+    // the floor shaders only light up around the first local ship
+    let transform = primary.single();
 
     if let Some(ship) = &materials.ship_material {
         ship_materials.get_mut(ship.id()).unwrap().player_position = transform.translation.xz();
@@ -1,113 +1,499 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::player::Player;
+use crate::{
+    buff::{Buff, Buffs},
+    constants::PICKUP_COLLECT_DISTANCE,
+    net::{
+        packet::{Connected, NetworkEvent, PowerupCollected, PowerupSpawned, PowerupState},
+        PlayerId, PlayerPeerId, ServerState,
+    },
+    pickup::{attract, Pickup},
+    player::Player,
+    scoreboard::RunStats,
+    simulation::SimulationSet,
+    stats::{Modifier, Stat, StatModifier, Stats},
+};
+
+pub const MAX_POWERUP_COUNT: usize = 64;
+/// Seconds a powerup stays on the map by default
+const POWERUP_LIFETIME: f32 = 15.0;
+/// Seconds before expiring that a powerup starts blinking
+const POWERUP_BLINK_TIME: f32 = 3.0;
 
 #[derive(Bundle)]
 pub struct PowerupBundle {
     pub powerup: Powerup,
-    pub scene: SceneBundle,
+    pub pickup: Pickup,
+    pub spatial: SpatialBundle,
 }
 
 #[derive(Component)]
 pub struct Powerup {
+    pub id: u32,
     pub powerup_type: PowerupType,
+    /// Seconds until it disappears
+    pub ttl: f32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum PowerupType {
     Health,
     Speed,
     Damage,
+    /// Pulls every pickup on the map to the player
+    Magnet,
+    RapidFire,
+    Shield,
+    DoubleDamage,
+    Freeze,
 }
 
 impl PowerupType {
-    pub fn random() -> Self {
-        match fastrand::u32(..3) {
+    pub fn random(rng: &mut fastrand::Rng) -> Self {
+        match rng.u32(..8) {
             0 => Self::Health,
             1 => Self::Speed,
             2 => Self::Damage,
+            3 => Self::Magnet,
+            4 => Self::RapidFire,
+            5 => Self::Shield,
+            6 => Self::DoubleDamage,
+            7 => Self::Freeze,
             _ => unreachable!(),
         }
     }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PowerupType::Health => "Health",
+            PowerupType::Speed => "Speed",
+            PowerupType::Damage => "Damage",
+            PowerupType::Magnet => "Magnet",
+            PowerupType::RapidFire => "Rapid fire",
+            PowerupType::Shield => "Shield",
+            PowerupType::DoubleDamage => "Double damage",
+            PowerupType::Freeze => "Freeze",
+        }
+    }
+
+    /// The magnet has no model file, it's built in [`startup`]
+    fn model(self) -> Option<&'static str> {
+        Some(match self {
+            PowerupType::Health => "food/apple.glb#Scene0",
+            PowerupType::Speed => "food/banana.glb#Scene0",
+            PowerupType::Damage => "food/cakeBirthday.glb#Scene0",
+            PowerupType::Magnet => return None,
+            // reusing the food models until newer powerups get their own
+            PowerupType::RapidFire => "food/banana.glb#Scene0",
+            PowerupType::Shield => "food/apple.glb#Scene0",
+            PowerupType::DoubleDamage => "food/cakeBirthday.glb#Scene0",
+            PowerupType::Freeze => "food/banana.glb#Scene0",
+        })
+    }
+
+    fn scale(self) -> Vec3 {
+        match self {
+            PowerupType::Health => Vec3::splat(5.0),
+            PowerupType::Magnet | PowerupType::Freeze => Vec3::splat(2.0),
+            PowerupType::Shield | PowerupType::DoubleDamage => Vec3::splat(4.0),
+            _ => Vec3::splat(3.0),
+        }
+    }
+
+    /// Timed effect started on pickup
+    pub fn buff(self) -> Option<Buff> {
+        match self {
+            PowerupType::Speed => Some(Buff::Speed),
+            PowerupType::RapidFire => Some(Buff::RapidFire),
+            PowerupType::Shield => Some(Buff::Shield),
+            PowerupType::DoubleDamage => Some(Buff::DoubleDamage),
+            PowerupType::Freeze => Some(Buff::Freeze),
+            PowerupType::Health | PowerupType::Damage | PowerupType::Magnet => None,
+        }
+    }
 }
 
+/// Asks the host for a powerup from the pool, dropped when every powerup is already on the map
 #[derive(Event)]
 pub struct PowerupSpawnEvent {
     pub powerup_type: PowerupType,
     pub transform: Transform,
 }
 
-pub struct PowerupPlugin;
+#[derive(Debug)]
+pub struct PowerupPlugin {
+    /// Seconds a powerup stays on the map before disappearing
+    pub lifetime: f32,
+}
+
+impl Default for PowerupPlugin {
+    fn default() -> Self {
+        Self {
+            lifetime: POWERUP_LIFETIME,
+        }
+    }
+}
 
 impl Plugin for PowerupPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<PowerupSpawnEvent>()
-            .add_systems(Update, update);
+            .insert_resource(PowerupLifetime(self.lifetime))
+            .add_systems(Startup, startup)
+            .add_systems(
+                Update,
+                (
+                    net_read,
+                    update.after(net_read),
+                    spawn_powerups.after(update),
+                    place_powerups.after(spawn_powerups),
+                    sync_joined,
+                ),
+            )
+            .add_systems(
+                FixedUpdate,
+                net_write.after(attract).in_set(SimulationSet::Update),
+            );
     }
 }
 
-fn update(
+#[derive(Resource)]
+struct PowerupLifetime(f32);
+
+/// A red ring, so the magnet doesn't look like any of the food
+#[derive(Resource)]
+struct MagnetScene(Handle<Scene>);
+
+fn startup(
     mut commands: Commands,
-    mut powerups: Query<(Entity, &mut Transform, &mut Powerup, &mut Visibility), Without<Player>>,
-    mut players: Query<(Entity, &mut Transform, &mut Player), Without<Powerup>>,
-    mut events: EventReader<PowerupSpawnEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut scenes: ResMut<Assets<Scene>>,
+) {
+    let mut world = World::new();
+    world.spawn(PbrBundle {
+        mesh: meshes.add(
+            shape::Torus {
+                radius: 0.2,
+                ring_radius: 0.06,
+                ..default()
+            }
+            .into(),
+        ),
+        material: materials.add(StandardMaterial {
+            base_color: Color::RED,
+            unlit: true,
+            ..default()
+        }),
+        ..default()
+    });
+    commands.insert_resource(MagnetScene(scenes.add(Scene::new(world))));
+
+    commands.spawn_batch((0..MAX_POWERUP_COUNT).map(|i| PowerupBundle {
+        powerup: Powerup {
+            id: i as u32,
+            powerup_type: PowerupType::Health,
+            ttl: 0.0,
+        },
+        pickup: Pickup::default(),
+        spatial: SpatialBundle {
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    }));
+}
+
+/// Effects on the player who collected the powerup
+fn apply(
+    powerup_type: PowerupType,
+    player: &mut Player,
+    stats: &mut Stats,
+    buffs: &mut Buffs,
+    run_stats: &mut RunStats,
+) {
+    run_stats.powerups += 1;
+    match powerup_type {
+        PowerupType::Health => {
+            player.health = (player.health + 10.0).min(stats.get(Stat::MaxHealth));
+        }
+        PowerupType::Damage => {
+            stats.add(StatModifier::permanent(Stat::Damage, Modifier::Add(0.15)));
+        }
+        // pickups are moved by the host, which pulls them in when it hands out a magnet
+        PowerupType::Magnet => {}
+        PowerupType::Speed
+        | PowerupType::RapidFire
+        | PowerupType::Shield
+        | PowerupType::DoubleDamage
+        | PowerupType::Freeze => {}
+    }
+    if let Some(buff) = powerup_type.buff() {
+        buffs.add(buff);
+        if let Some((stat, modifier)) = buff.modifier() {
+            stats.refresh(StatModifier::timed(stat, modifier, buff.duration()));
+        }
+    }
+}
+
+/// Everyone counts down and blinks powerups, only the host decides who picked one up so two
+/// players can't both claim it
+fn update(
+    status: Res<ServerState>,
+    player_id: Res<PlayerId>,
     time: Res<Time>,
-    server: Res<AssetServer>,
+    mut powerups: Query<(&mut Transform, &mut Powerup, &mut Visibility), Without<Player>>,
+    mut players: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Player,
+            &mut Stats,
+            &mut Buffs,
+            &mut RunStats,
+        ),
+        Without<Powerup>,
+    >,
+    peers: Query<(Entity, &Transform, &PlayerPeerId), Without<Powerup>>,
+    mut pickups: Query<&mut Pickup>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
 ) {
-    for (powerup_entity, mut powerup_transform, powerup, mut vis) in powerups.iter_mut() {
+    let mut magnet = None;
+    for (mut powerup_transform, mut powerup, mut vis) in powerups.iter_mut() {
+        if *vis == Visibility::Hidden {
+            continue;
+        }
+
+        powerup.ttl -= time.delta_seconds();
+        if powerup.ttl <= 0.0 {
+            *vis = Visibility::Hidden;
+            continue;
+        }
+
         powerup_transform.rotation = Quat::from_axis_angle(Vec3::Y, time.elapsed_seconds() * 2.0);
+        // blink twice a second when about to expire, visibility marks free pool entries so
+        // scale it away instead
+        let blink = powerup.ttl < POWERUP_BLINK_TIME && (powerup.ttl * 2.0).fract() < 0.5;
+        powerup_transform.scale = if blink {
+            Vec3::ZERO
+        } else {
+            powerup.powerup_type.scale()
+        };
 
-        for (player_entity, player_transform, mut player) in players.iter_mut() {
-            if *vis != Visibility::Hidden {
-                if (player_transform.translation.xz() - powerup_transform.translation.xz()).length()
-                    < 1.0
-                {
-                    match powerup.powerup_type {
-                        PowerupType::Health => {
-                            player.health += 10.0;
-                        }
-                        PowerupType::Speed => {
-                            // player.speed += PLAYER_SPEED * 0.1;
-                        }
-                        PowerupType::Damage => {
-                            player.damage += 0.15;
-                        }
-                    }
-                    *vis = Visibility::Hidden;
-                }
-            }
+        if *status == ServerState::Client {
+            continue;
+        }
+
+        let position = powerup_transform.translation.xz();
+        let in_reach = |transform: &Transform| {
+            transform.translation.xz().distance(position) < PICKUP_COLLECT_DISTANCE
+        };
+        let collector = if let Some((entity, _, mut player, mut stats, mut buffs, mut run_stats)) =
+            players
+                .iter_mut()
+                .find(|(_, transform, ..)| in_reach(transform))
+        {
+            apply(
+                powerup.powerup_type,
+                &mut player,
+                &mut stats,
+                &mut buffs,
+                &mut run_stats,
+            );
+            Some((entity, player_id.0))
+        } else {
+            peers
+                .iter()
+                .find(|(_, transform, _)| in_reach(transform))
+                .map(|(entity, _, peer_id)| (entity, Some(peer_id.0)))
+        };
+        let Some((entity, collector)) = collector else {
+            continue;
+        };
+
+        if powerup.powerup_type == PowerupType::Magnet {
+            magnet = Some(entity);
+        }
+        if let Some(collector) = collector {
+            net_event_writer.send(NetworkEvent::PowerupCollected(PowerupCollected {
+                id: powerup.id as u16,
+                collector,
+            }));
+        }
+        *vis = Visibility::Hidden;
+    }
+
+    if let Some(player_entity) = magnet {
+        for mut pickup in pickups.iter_mut() {
+            pickup.attract(player_entity);
         }
     }
+}
+
+/// The host picks a free powerup from the pool and tells everyone to place it
+fn spawn_powerups(
+    status: Res<ServerState>,
+    mut events: EventReader<PowerupSpawnEvent>,
+    lifetime: Res<PowerupLifetime>,
+    powerups: Query<(&Powerup, &Visibility)>,
+    mut spawned_writer: EventWriter<PowerupSpawned>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    if *status == ServerState::Client {
+        return;
+    }
+
+    let mut pool = powerups
+        .iter()
+        .filter(|(_, visibility)| **visibility == Visibility::Hidden);
 
     for event in events.read() {
-        spawn_powerup(event.powerup_type, event.transform, &mut commands, &server);
+        let Some((powerup, _)) = pool.next() else {
+            continue;
+        };
+
+        let spawned = PowerupSpawned {
+            id: powerup.id as u16,
+            powerup_type: event.powerup_type,
+            position: event.transform.translation,
+            ttl: lifetime.0,
+        };
+        net_event_writer.send(NetworkEvent::PowerupSpawned(spawned.clone()));
+        spawned_writer.send(spawned);
     }
 }
 
-pub fn spawn_powerup(
-    powerup_type: PowerupType,
-    transform: Transform,
-    commands: &mut Commands,
-    server: &Res<AssetServer>,
-) -> Entity {
-    let scene = match &powerup_type {
-        PowerupType::Health => server.load("food/apple.glb#Scene0"),
-        PowerupType::Speed => server.load("food/banana.glb#Scene0"),
-        PowerupType::Damage => server.load("food/cakeBirthday.glb#Scene0"),
-    };
-
-    commands
-        .spawn(PowerupBundle {
-            powerup: Powerup { powerup_type },
-            scene: SceneBundle {
-                scene: scene.clone(),
-                transform: transform.with_scale(if powerup_type == PowerupType::Health {
-                    Vec3::splat(5.0)
-                } else {
-                    Vec3::splat(3.0)
+fn place_powerups(
+    mut commands: Commands,
+    mut events: EventReader<PowerupSpawned>,
+    server: Res<AssetServer>,
+    magnet: Res<MagnetScene>,
+    mut powerups: Query<(
+        Entity,
+        &mut Transform,
+        &mut Powerup,
+        &mut Pickup,
+        &mut Visibility,
+        Option<&Handle<Scene>>,
+    )>,
+) {
+    for event in events.read() {
+        let Some((entity, mut transform, mut powerup, mut pickup, mut visibility, scene)) =
+            powerups
+                .iter_mut()
+                .find(|(_, _, powerup, ..)| powerup.id == event.id as u32)
+        else {
+            continue;
+        };
+
+        // swapping the scene respawns its children, so only do it when the model changes
+        let model = match event.powerup_type.model() {
+            Some(path) => server.load(path),
+            None => magnet.0.clone(),
+        };
+        if scene != Some(&model) {
+            commands.entity(entity).insert(model);
+        }
+
+        *visibility = Visibility::Visible;
+        *transform =
+            Transform::from_translation(event.position).with_scale(event.powerup_type.scale());
+        powerup.powerup_type = event.powerup_type;
+        powerup.ttl = event.ttl;
+        pickup.reset();
+    }
+}
+
+/// The host tells players that just joined about the powerups already on the map
+fn sync_joined(
+    status: Res<ServerState>,
+    mut connected: EventReader<Connected>,
+    powerups: Query<(&Transform, &Powerup, &Visibility)>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    let joined = connected.read().count() > 0;
+    if !joined || *status != ServerState::Host {
+        return;
+    }
+
+    // the others get these again too, which only moves their powerups to where they already are
+    net_event_writer.send_batch(
+        powerups
+            .iter()
+            .filter(|(_, _, visibility)| **visibility != Visibility::Hidden)
+            .map(|(transform, powerup, _)| {
+                NetworkEvent::PowerupSpawned(PowerupSpawned {
+                    id: powerup.id as u16,
+                    powerup_type: powerup.powerup_type,
+                    position: transform.translation,
+                    ttl: powerup.ttl,
+                })
+            }),
+    );
+}
+
+fn net_write(
+    status: Res<ServerState>,
+    powerups: Query<(&Transform, &Powerup, &Visibility)>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    if *status == ServerState::Host {
+        net_event_writer.send_batch(
+            powerups
+                .iter()
+                .filter(|(_, _, visibility)| **visibility != Visibility::Hidden)
+                .map(|(transform, powerup, _)| {
+                    NetworkEvent::PowerupState(PowerupState {
+                        id: powerup.id as u16,
+                        position: transform.translation.xz(),
+                    })
                 }),
-                ..default()
-            },
-        })
-        .id()
+        );
+    }
+}
+
+/// Clients move powerups where the host has them, hide the ones it handed out and apply the ones
+/// it handed to them
+fn net_read(
+    status: Res<ServerState>,
+    player_id: Res<PlayerId>,
+    mut states: EventReader<PowerupState>,
+    mut collected: EventReader<PowerupCollected>,
+    mut powerups: Query<(&Powerup, &mut Transform, &mut Visibility)>,
+    mut players: Query<(&mut Player, &mut Stats, &mut Buffs, &mut RunStats), Without<PlayerPeerId>>,
+) {
+    if *status != ServerState::Client {
+        return;
+    }
+
+    for event in states.read() {
+        if let Some((_, mut transform, _)) = powerups
+            .iter_mut()
+            .find(|(powerup, _, _)| powerup.id == event.id as u32)
+        {
+            transform.translation.x = event.position.x;
+            transform.translation.z = event.position.y;
+        }
+    }
+
+    for event in collected.read() {
+        let Some((powerup, _, mut visibility)) = powerups
+            .iter_mut()
+            .find(|(powerup, ..)| powerup.id == event.id as u32)
+        else {
+            continue;
+        };
+        *visibility = Visibility::Hidden;
+
+        if Some(event.collector) == player_id.0 {
+            for (mut player, mut stats, mut buffs, mut run_stats) in &mut players {
+                apply(
+                    powerup.powerup_type,
+                    &mut player,
+                    &mut stats,
+                    &mut buffs,
+                    &mut run_stats,
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,328 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{app::AppExit, prelude::*};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    experience::LevelUp,
+    input::{Action, ActionState},
+    player::{Player, PrimaryPlayer},
+    ship::ShipType,
+    stats::Stat,
+    ui::MenuState,
+    weapon::WeaponType,
+};
+
+const CURRENCY_PER_LEVEL: u32 = 10;
+const CURRENCY_PER_MINUTE: u32 = 5;
+
+/// Keys that buy the listed shop entries, with the label shown next to each, one per unlock
+pub const SHOP_KEYS: [(KeyCode, &str); Unlock::ALL.len()] = [
+    (KeyCode::Key1, "1"),
+    (KeyCode::Key2, "2"),
+    (KeyCode::Key3, "3"),
+    (KeyCode::Key4, "4"),
+    (KeyCode::Key5, "5"),
+    (KeyCode::Key6, "6"),
+    (KeyCode::Key7, "7"),
+    (KeyCode::Key8, "8"),
+    (KeyCode::Key9, "9"),
+    (KeyCode::Key0, "0"),
+    (KeyCode::Minus, "-"),
+];
+
+/// Progress kept between runs. Currency is banked at game over, or when the game exits mid-run,
+/// and spent in the unlock shop, toggled with [`Action::Interact`]
+#[derive(Debug)]
+pub struct SavePlugin {
+    /// Where the save is written, `None` keeps it in memory only
+    pub path: Option<PathBuf>,
+    pub save: Save,
+}
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.save.clone())
+            .add_systems(Update, shop.run_if(in_state(MenuState::Closed)))
+            .add_systems(Last, end_run);
+
+        if let Some(path) = &self.path {
+            app.insert_resource(SavePath(path.clone()));
+        }
+    }
+}
+
+#[derive(Resource)]
+struct SavePath(PathBuf);
+
+/// Present while the unlock shop is open
+#[derive(Debug, Resource)]
+pub struct Shop;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Unlock {
+    /// Lets the weapon show up in level up choices
+    Weapon(WeaponType),
+    /// Starts every run with the stat's level up upgrade
+    StartingUpgrade(Stat),
+    Ship(ShipType),
+}
+
+impl Unlock {
+    pub const ALL: [Unlock; 11] = [
+        Unlock::Ship(ShipType::Interceptor),
+        Unlock::Ship(ShipType::Tank),
+        Unlock::Weapon(WeaponType::Shotgun),
+        Unlock::Weapon(WeaponType::LaserBeam),
+        Unlock::Weapon(WeaponType::HomingMissiles),
+        Unlock::Weapon(WeaponType::OrbitingBlades),
+        Unlock::Weapon(WeaponType::AreaPulse),
+        Unlock::StartingUpgrade(Stat::MaxHealth),
+        Unlock::StartingUpgrade(Stat::MaxSpeed),
+        Unlock::StartingUpgrade(Stat::Damage),
+        Unlock::StartingUpgrade(Stat::FireRate),
+    ];
+
+    pub fn cost(self) -> u32 {
+        match self {
+            Unlock::Weapon(_) => 50,
+            Unlock::StartingUpgrade(_) => 100,
+            Unlock::Ship(_) => 150,
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Unlock::Weapon(weapon_type) => weapon_type.label().to_string(),
+            Unlock::StartingUpgrade(stat) => format!("Start with {}", stat.upgrade().label(stat)),
+            Unlock::Ship(ship_type) => format!("{} ship", ship_type.label()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Seconds survived, not counting pauses
+    pub seconds: f32,
+    pub level: u32,
+    pub currency: u32,
+}
+
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Save {
+    pub currency: u32,
+    pub runs: Vec<RunRecord>,
+    pub unlocks: Vec<Unlock>,
+}
+
+/// Reads a file written by [`write`], a missing or unreadable file gives the default
+pub fn read<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return T::default();
+    };
+    ron::from_str(&contents).unwrap_or_else(|err| {
+        warn!(?path, %err, "failed to read file");
+        T::default()
+    })
+}
+
+/// Writes the value as RON, creating its directory when missing
+pub fn write<T: Serialize>(value: &T, path: &Path) {
+    let result = ron::ser::to_string_pretty(value, default())
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            fs::write(path, contents).map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        warn!(?path, %err, "failed to write file");
+    }
+}
+
+impl Save {
+    pub fn has(&self, unlock: Unlock) -> bool {
+        self.unlocks.contains(&unlock)
+    }
+
+    /// The starting weapon is always available
+    pub fn has_weapon(&self, weapon_type: WeaponType) -> bool {
+        weapon_type == WeaponType::TwinBlaster || self.has(Unlock::Weapon(weapon_type))
+    }
+
+    /// The default ship is always available
+    pub fn has_ship(&self, ship_type: ShipType) -> bool {
+        ship_type == ShipType::default() || self.has(Unlock::Ship(ship_type))
+    }
+
+    pub fn starting_upgrades(&self) -> impl Iterator<Item = Stat> + '_ {
+        self.unlocks.iter().filter_map(|unlock| match unlock {
+            Unlock::StartingUpgrade(stat) => Some(*stat),
+            Unlock::Weapon(_) | Unlock::Ship(_) => None,
+        })
+    }
+
+    /// Unlocks not bought yet, in the order the shop lists them
+    pub fn for_sale(&self) -> impl Iterator<Item = Unlock> + '_ {
+        Unlock::ALL.into_iter().filter(|unlock| !self.has(*unlock))
+    }
+
+    /// Spends currency on the unlock, returns whether it was bought
+    pub fn buy(&mut self, unlock: Unlock) -> bool {
+        if self.has(unlock) || self.currency < unlock.cost() {
+            return false;
+        }
+        self.currency -= unlock.cost();
+        self.unlocks.push(unlock);
+        true
+    }
+
+    pub fn best_level(&self) -> u32 {
+        self.runs.iter().map(|run| run.level).max().unwrap_or(0)
+    }
+
+    pub fn seconds_played(&self) -> f32 {
+        self.runs.iter().map(|run| run.seconds).sum()
+    }
+}
+
+/// The game's directory in the platform's config directory
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    dir.map(|dir| dir.join("bevy-jam-4"))
+}
+
+fn shop(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
+    shop: Option<Res<Shop>>,
+    level_up: Option<Res<LevelUp>>,
+    path: Option<Res<SavePath>>,
+    mut save: ResMut<Save>,
+) {
+    // the number keys pick upgrades while leveling up
+    if level_up.is_some() {
+        if shop.is_some() {
+            commands.remove_resource::<Shop>();
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::Interact) {
+        match shop {
+            Some(_) => commands.remove_resource::<Shop>(),
+            None => commands.insert_resource(Shop),
+        }
+        return;
+    }
+    if shop.is_none() {
+        return;
+    }
+
+    let choices = save.for_sale().collect::<Vec<_>>();
+    let Some(unlock) = keys
+        .get_just_pressed()
+        .find_map(|key| SHOP_KEYS.iter().position(|(shop_key, _)| shop_key == key))
+        .and_then(|index| choices.get(index).copied())
+    else {
+        return;
+    };
+
+    if save.buy(unlock) {
+        info!(?unlock, "unlocked");
+        if let Some(path) = path {
+            write(&*save, &path.0);
+        }
+    }
+}
+
+/// Banks the currency earned this run once, at game over or when the game exits
+fn end_run(
+    mut exit: EventReader<AppExit>,
+    state: Res<State<MenuState>>,
+    mut banked: Local<bool>,
+    time: Res<Time<Virtual>>,
+    path: Option<Res<SavePath>>,
+    mut save: ResMut<Save>,
+    players: Query<&Player, With<PrimaryPlayer>>,
+) {
+    let exiting = exit.read().last().is_some();
+    if *banked || !(exiting || *state.get() == MenuState::GameOver) {
+        return;
+    }
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    *banked = true;
+
+    let seconds = time.elapsed_seconds();
+    let currency = player.level.saturating_sub(1) * CURRENCY_PER_LEVEL
+        + (seconds / 60.0) as u32 * CURRENCY_PER_MINUTE;
+    save.currency += currency;
+    save.runs.push(RunRecord {
+        seconds,
+        level: player.level,
+        currency,
+    });
+    info!(currency, total = save.currency, "run ended");
+
+    if let Some(path) = path {
+        write(&*save, &path.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Save, Unlock};
+    use crate::{ship::ShipType, stats::Stat, weapon::WeaponType};
+
+    #[test]
+    fn test_buy_and_round_trip() {
+        let unlock = Unlock::Weapon(WeaponType::Shotgun);
+        let mut save = Save {
+            currency: unlock.cost(),
+            ..Default::default()
+        };
+        assert!(!save.has_weapon(WeaponType::Shotgun));
+        assert!(save.buy(unlock));
+        assert!(!save.buy(unlock));
+        assert_eq!(save.currency, 0);
+
+        let save: Save = ron::from_str(&ron::to_string(&save).unwrap()).unwrap();
+        assert!(save.has_weapon(WeaponType::Shotgun));
+        assert_eq!(save.for_sale().count(), Unlock::ALL.len() - 1);
+    }
+
+    #[test]
+    fn test_costs_and_full_save_round_trip() {
+        assert_eq!(Unlock::Weapon(WeaponType::Shotgun).cost(), 50);
+        assert_eq!(Unlock::StartingUpgrade(Stat::MaxSpeed).cost(), 100);
+        assert_eq!(Unlock::Ship(ShipType::Tank).cost(), 150);
+
+        let mut save = Save {
+            currency: Unlock::ALL.iter().map(|unlock| unlock.cost()).sum(),
+            ..Default::default()
+        };
+        assert!(Unlock::ALL.into_iter().all(|unlock| save.buy(unlock)));
+        assert_eq!(save.currency, 0);
+
+        let save: Save = ron::from_str(&ron::to_string(&save).unwrap()).unwrap();
+        assert_eq!(save.for_sale().count(), 0);
+        assert!(save.has_ship(ShipType::Tank));
+        assert_eq!(save.starting_upgrades().count(), 4);
+    }
+}
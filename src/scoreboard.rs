@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enemy::{DamageDealt, EnemyDied, SpawnGeneration},
+    net::{
+        packet::{NetworkEvent, RunStatsState},
+        PlayerId, PlayerPeerId,
+    },
+    player::{Player, PlayerName, PrimaryPlayer},
+    simulation::SimulationSet,
+    ui::MenuState,
+};
+
+/// Seconds between sending our statistics to peers
+const SEND_INTERVAL: f32 = 1.0;
+
+/// Statistics every player keeps for their own ship and shares with the others. The run ends
+/// when every local ship runs out of health, the game over screen shows everyone's and can export
+/// them as JSON
+#[derive(Debug)]
+pub struct ScoreboardPlugin {
+    /// Where exported scoreboards are written, `None` disables exporting
+    pub export_dir: Option<PathBuf>,
+}
+
+impl Plugin for ScoreboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportScoreboard>()
+            .add_systems(Update, (track, game_over.after(track), net_read, export))
+            .add_systems(FixedUpdate, net_write.in_set(SimulationSet::Update));
+
+        if let Some(dir) = &self.export_dir {
+            app.insert_resource(ExportDir(dir.clone()));
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ExportDir(PathBuf);
+
+/// Writes everyone's statistics to a new file in the export directory
+#[derive(Event, Debug)]
+pub struct ExportScoreboard;
+
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Enemies the host saw die after this player's hit
+    pub kills: u32,
+    pub damage_dealt: f32,
+    pub damage_taken: f32,
+    pub powerups: u32,
+    /// Seconds alive, not counting pauses
+    pub seconds: f32,
+    pub highest_wave: u32,
+}
+
+impl RunStats {
+    pub fn label(&self) -> String {
+        let seconds = self.seconds as u32;
+        format!(
+            "{} kills   {:.0} damage dealt   {:.0} taken   {} powerups   {:02}:{:02}   wave {}",
+            self.kills,
+            self.damage_dealt,
+            self.damage_taken,
+            self.powerups,
+            seconds / 60,
+            seconds % 60,
+            self.highest_wave
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ScoreboardEntry<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    stats: &'a RunStats,
+}
+
+/// Counts the local players' hits, kills and time alive, damage taken and powerups are counted
+/// where they're applied
+fn track(
+    time: Res<Time>,
+    spawn_generation: Res<SpawnGeneration>,
+    mut damage_dealt: EventReader<DamageDealt>,
+    mut died: EventReader<EnemyDied>,
+    mut players: Query<(&Player, &mut RunStats), Without<PlayerPeerId>>,
+) {
+    for event in damage_dealt.read() {
+        let Some(Ok((player, mut stats))) = event.source.map(|source| players.get_mut(source))
+        else {
+            continue;
+        };
+        if player.health <= 0.0 {
+            continue;
+        }
+        stats.damage_dealt += event.landed;
+    }
+
+    // only deaths the host confirmed, a client's own count of the health can be off
+    for event in died.read() {
+        if let Some(Ok((_, mut stats))) = event.killer.map(|killer| players.get_mut(killer)) {
+            stats.kills += 1;
+        }
+    }
+
+    for (player, mut stats) in &mut players {
+        if player.health > 0.0 {
+            stats.seconds += time.delta_seconds();
+            stats.highest_wave = stats.highest_wave.max(spawn_generation.0 as u32);
+        }
+    }
+}
+
+fn game_over(
+    state: Res<State<MenuState>>,
+    mut next_state: ResMut<NextState<MenuState>>,
+    players: Query<&Player, Without<PlayerPeerId>>,
+) {
+    let all_dead = !players.is_empty() && players.iter().all(|player| player.health <= 0.0);
+    if all_dead && *state.get() == MenuState::Closed {
+        info!("game over");
+        next_state.set(MenuState::GameOver);
+    }
+}
+
+fn net_write(
+    time: Res<Time>,
+    player_id: Res<PlayerId>,
+    mut timer: Local<f32>,
+    players: Query<&RunStats, With<PrimaryPlayer>>,
+    mut net_event_writer: EventWriter<NetworkEvent>,
+) {
+    *timer -= time.delta_seconds();
+    let (Some(id), Ok(stats), true) = (player_id.0, players.get_single(), *timer <= 0.0) else {
+        return;
+    };
+    *timer = SEND_INTERVAL;
+    net_event_writer.send(NetworkEvent::RunStatsState(RunStatsState {
+        id,
+        stats: stats.clone(),
+    }));
+}
+
+fn net_read(
+    mut events: EventReader<RunStatsState>,
+    mut peers: Query<(&mut RunStats, &PlayerPeerId)>,
+) {
+    for event in events.read() {
+        for (mut stats, PlayerPeerId(id)) in &mut peers {
+            if id == &event.id {
+                *stats = event.stats.clone();
+            }
+        }
+    }
+}
+
+fn export(
+    mut events: EventReader<ExportScoreboard>,
+    dir: Option<Res<ExportDir>>,
+    players: Query<(&PlayerName, &RunStats)>,
+) {
+    if events.read().last().is_none() {
+        return;
+    }
+    let Some(dir) = dir else {
+        warn!("no directory to export the scoreboard to");
+        return;
+    };
+
+    let entries = players
+        .iter()
+        .map(|(name, stats)| ScoreboardEntry {
+            name: &name.0,
+            stats,
+        })
+        .collect::<Vec<_>>();
+    // milliseconds so exporting twice in a second doesn't overwrite the first
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+    let path = dir.0.join(format!("scoreboard-{timestamp}.json"));
+
+    let result = serde_json::to_string_pretty(&entries)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            fs::create_dir_all(&dir.0).map_err(|err| err.to_string())?;
+            fs::write(&path, contents).map_err(|err| err.to_string())
+        });
+    match result {
+        Ok(()) => info!(?path, "scoreboard exported"),
+        Err(err) => warn!(?path, %err, "failed to export scoreboard"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{track, RunStats, ScoreboardEntry};
+    use crate::{
+        enemy::{DamageDealt, EnemyDied, SpawnGeneration},
+        player::Player,
+    };
+
+    #[test]
+    fn test_track_counts_landed_damage_and_confirmed_kills() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(SpawnGeneration(2))
+            .add_event::<DamageDealt>()
+            .add_event::<EnemyDied>()
+            .add_systems(Update, track);
+        let player = app
+            .world
+            .spawn((
+                Player {
+                    health: 10.0,
+                    invulnerability: 0.0,
+                    experience: 0.0,
+                    level: 1,
+                },
+                RunStats::default(),
+            ))
+            .id();
+
+        let hit = |landed| DamageDealt {
+            id: 0,
+            position: Vec3::ZERO,
+            amount: 5.0,
+            landed,
+            source: Some(player),
+        };
+        // the second hit overkills, the third lands on an enemy already out of health
+        app.world.send_event(hit(5.0));
+        app.world.send_event(hit(3.0));
+        app.world.send_event(hit(0.0));
+        app.world.send_event(EnemyDied {
+            position: Vec3::ZERO,
+            boss: false,
+            killer: Some(player),
+        });
+        app.world.send_event(EnemyDied {
+            position: Vec3::ZERO,
+            boss: false,
+            killer: None,
+        });
+        app.update();
+
+        let stats = app.world.get::<RunStats>(player).unwrap();
+        assert_eq!(stats.damage_dealt, 8.0);
+        assert_eq!(stats.kills, 1);
+        assert_eq!(stats.highest_wave, 2);
+    }
+
+    #[test]
+    fn test_entry_is_flat() {
+        let stats = RunStats {
+            kills: 3,
+            ..Default::default()
+        };
+        let entry = ScoreboardEntry {
+            name: "Pilot",
+            stats: &stats,
+        };
+
+        let json: serde_json::Value = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["name"], "Pilot");
+        assert_eq!(json["kills"], 3);
+    }
+}
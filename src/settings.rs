@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use bevy::{
+    prelude::*,
+    window::{PresentMode, PrimaryWindow, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{input::Bindings, player::FireMode, save, ship::ShipType};
+
+/// Player preferences, applied whenever they change and written next to the save
+#[derive(Debug)]
+pub struct SettingsPlugin {
+    /// Where the settings are written, `None` keeps them in memory only
+    pub path: Option<PathBuf>,
+    pub settings: Settings,
+}
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone())
+            .add_systems(Update, apply);
+
+        if let Some(path) = &self.path {
+            app.insert_resource(SettingsPath(path.clone()));
+        }
+    }
+}
+
+#[derive(Resource)]
+struct SettingsPath(PathBuf);
+
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Shown to other players
+    pub name: String,
+    /// Given with `--name`, used instead of the saved name for this session only
+    #[serde(skip)]
+    pub name_override: Option<String>,
+    /// Picked in the main menu
+    pub ship: ShipType,
+    /// Given with `--ship` or by a replay, flown instead of the saved ship for this session only
+    #[serde(skip)]
+    pub ship_override: Option<ShipType>,
+    /// 0 to 1
+    pub volume: f32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    /// Frame every living player instead of following our own ship
+    pub shared_camera: bool,
+    /// The first local player's, couch co-op players start with it too
+    pub fire_mode: FireMode,
+    pub bindings: Bindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            name: "Pilot".to_string(),
+            name_override: None,
+            ship: ShipType::default(),
+            ship_override: None,
+            volume: 1.0,
+            fullscreen: false,
+            vsync: true,
+            shared_camera: false,
+            fire_mode: FireMode::default(),
+            bindings: Bindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// The name we fly under
+    pub fn name(&self) -> &str {
+        self.name_override.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The ship we fly
+    pub fn ship(&self) -> ShipType {
+        self.ship_override.unwrap_or(self.ship)
+    }
+}
+
+fn apply(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    path: Option<Res<SettingsPath>>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    commands.insert_resource(GlobalVolume::new(settings.volume));
+    for mut window in &mut windows {
+        window.mode = if settings.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        };
+        window.present_mode = if settings.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        };
+    }
+
+    // loading them isn't a change worth writing
+    if let (Some(path), false) = (path, settings.is_added()) {
+        save::write(&*settings, &path.0);
+    }
+}
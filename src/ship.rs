@@ -1,26 +1,43 @@
-use bevy::prelude::*;
+use bevy::{
+    gltf::{Gltf, GltfNode},
+    prelude::*,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
 use crate::materials::ShipMaterial;
 use crate::{
+    constants::{
+        KNOCKBACK_DECAY_RATE, PLAYER_ACCELERATION_RATE, PLAYER_DRAG_COEFFICIENT, PLAYER_MAX_HEALTH,
+        PLAYER_MAX_SPEED, PLAYER_RADIUS,
+    },
     net::{
         packet::{NetworkEvent, PlayerState},
         PlayerId, PlayerPeerId, ServerState,
     },
-    player::Player,
+    player::{Player, TeammateHealth},
+    simulation::{Interpolated, SimulationSet},
+    stats::{Stat, Stats},
+    weapon::WeaponType,
 };
 
+/// Where projectiles leave a ship whose model has no muzzle nodes, in ship space
+const DEFAULT_MUZZLES: [Vec3; 2] = [Vec3::new(0.65, 0.0, -0.3), Vec3::new(-0.65, 0.0, -0.3)];
+
 pub struct ShipPlugin;
 
 impl Plugin for ShipPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_systems(
-            Update,
-            (
-                net_read,
-                update_transforms.after(net_read),
-                net_write.after(update_transforms),
-            ),
-        );
+        app.add_systems(Update, (net_read, load_muzzles))
+            .add_systems(
+                FixedUpdate,
+                (
+                    update_transforms,
+                    resolve_collisions.after(update_transforms),
+                    net_write.after(resolve_collisions),
+                )
+                    .in_set(SimulationSet::Update),
+            );
     }
 }
 
@@ -28,6 +45,104 @@ impl Plugin for ShipPlugin {
 pub struct ShipBundle {
     pub ship: Ship,
     pub material_mesh: MaterialMeshBundle<ShipMaterial>,
+    pub interpolated: Interpolated,
+    pub muzzles: Muzzles,
+}
+
+impl ShipBundle {
+    /// A playable ship, the stats it starts with are set up by whoever spawns it
+    pub fn player(
+        ship_type: ShipType,
+        server: &AssetServer,
+        material: Handle<ShipMaterial>,
+    ) -> Self {
+        let spec = ship_type.spec();
+        Self {
+            ship: spec.ship(),
+            material_mesh: MaterialMeshBundle {
+                mesh: spec.mesh(server),
+                material,
+                ..default()
+            },
+            interpolated: Interpolated::default(),
+            muzzles: Muzzles::new(server.load(spec.model)),
+        }
+    }
+}
+
+/// Playable ships, picked in the main menu or with `--ship` and sent to peers when connecting
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Component, Serialize, Deserialize, ValueEnum,
+)]
+pub enum ShipType {
+    #[default]
+    Fighter,
+    /// Fast and fragile
+    Interceptor,
+    /// Slow and sturdy
+    Tank,
+}
+
+impl ShipType {
+    pub const ALL: [ShipType; 3] = [ShipType::Fighter, ShipType::Interceptor, ShipType::Tank];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShipType::Fighter => "Fighter",
+            ShipType::Interceptor => "Interceptor",
+            ShipType::Tank => "Tank",
+        }
+    }
+
+    pub fn spec(self) -> ShipSpec {
+        match self {
+            ShipType::Fighter => ShipSpec {
+                model: "player2.glb",
+                max_speed: PLAYER_MAX_SPEED,
+                acceleration: PLAYER_ACCELERATION_RATE,
+                drag: PLAYER_DRAG_COEFFICIENT,
+                health: PLAYER_MAX_HEALTH,
+                weapon: WeaponType::TwinBlaster,
+            },
+            ShipType::Interceptor => ShipSpec {
+                model: "ship1.glb",
+                max_speed: PLAYER_MAX_SPEED * 1.3,
+                acceleration: PLAYER_ACCELERATION_RATE * 1.4,
+                drag: PLAYER_DRAG_COEFFICIENT,
+                health: PLAYER_MAX_HEALTH * 0.7,
+                weapon: WeaponType::Shotgun,
+            },
+            ShipType::Tank => ShipSpec {
+                model: "player.glb",
+                max_speed: PLAYER_MAX_SPEED * 0.8,
+                acceleration: PLAYER_ACCELERATION_RATE * 0.8,
+                drag: PLAYER_DRAG_COEFFICIENT * 1.25,
+                health: PLAYER_MAX_HEALTH * 1.5,
+                weapon: WeaponType::OrbitingBlades,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShipSpec {
+    /// glTF holding the mesh and its muzzle nodes
+    pub model: &'static str,
+    pub max_speed: f32,
+    pub acceleration: f32,
+    pub drag: f32,
+    pub health: f32,
+    pub weapon: WeaponType,
+}
+
+impl ShipSpec {
+    pub fn ship(&self) -> Ship {
+        Ship::new(self.max_speed, self.acceleration, self.drag, PLAYER_RADIUS)
+    }
+
+    pub fn mesh(&self, server: &AssetServer) -> Handle<Mesh> {
+        server.load(format!("{}#Mesh0/Primitive0", self.model))
+    }
 }
 
 #[derive(Clone, Component, Default)]
@@ -39,14 +154,16 @@ pub struct Ship {
     pub max_speed: f32,
     pub acceleration_rate: f32,
     pub drag_coefficient: f32,
+    pub radius: f32,
 }
 
 impl Ship {
-    pub fn new(max_speed: f32, acceleration_rate: f32, drag_coefficient: f32) -> Self {
+    pub fn new(max_speed: f32, acceleration_rate: f32, drag_coefficient: f32, radius: f32) -> Self {
         Self {
             max_speed,
             acceleration_rate,
             drag_coefficient,
+            radius,
             ..Default::default()
         }
     }
@@ -54,21 +171,89 @@ impl Ship {
     pub fn velocity(&self) -> Vec3 {
         self.velocity
     }
+
+    /// Instantly changes velocity, allowing the ship to exceed its max speed until drag bleeds it off
+    pub fn apply_impulse(&mut self, impulse: Vec3) {
+        self.velocity += impulse;
+    }
+}
+
+/// Points projectiles leave the ship from, read from the nodes named `Muzzle1`, `Muzzle2`... in
+/// the ship's glTF in number order. Muzzle nodes are placed relative to the ship mesh
+#[derive(Clone, Component)]
+pub struct Muzzles {
+    gltf: Handle<Gltf>,
+    points: Vec<Vec3>,
+    loaded: bool,
+}
+
+impl Muzzles {
+    pub fn new(gltf: Handle<Gltf>) -> Self {
+        Self {
+            gltf,
+            points: DEFAULT_MUZZLES.to_vec(),
+            loaded: false,
+        }
+    }
+
+    /// Cycles through the muzzles, in ship space
+    pub fn get(&self, index: u32) -> Vec3 {
+        self.points[index as usize % self.points.len()]
+    }
+
+    /// The middle of the muzzles, for weapons that go off all around the ship
+    pub fn center(&self) -> Vec3 {
+        self.points.iter().sum::<Vec3>() / self.points.len() as f32
+    }
+}
+
+fn load_muzzles(
+    gltfs: Res<Assets<Gltf>>,
+    nodes: Res<Assets<GltfNode>>,
+    mut query: Query<&mut Muzzles>,
+) {
+    for mut muzzles in query.iter_mut().filter(|muzzles| !muzzles.loaded) {
+        let Some(gltf) = gltfs.get(&muzzles.gltf) else {
+            continue;
+        };
+
+        let mut named = gltf
+            .named_nodes
+            .iter()
+            .filter_map(|(name, node)| {
+                let number = name.strip_prefix("Muzzle")?.parse::<u32>().ok()?;
+                Some((number, node))
+            })
+            .collect::<Vec<_>>();
+        named.sort_by_key(|(number, _)| *number);
+        let points = named
+            .into_iter()
+            .filter_map(|(_, node)| nodes.get(node))
+            .map(|node| node.transform.translation)
+            .collect::<Vec<_>>();
+
+        if !points.is_empty() {
+            muzzles.points = points;
+        }
+        muzzles.loaded = true;
+    }
 }
 
 fn net_write(
     status: Res<ServerState>,
     mut write_player_state: EventWriter<NetworkEvent>,
-    player_query: Query<(&Player, &Transform, Option<&PlayerPeerId>)>,
+    player_query: Query<(&Player, &Stats, &Transform, Option<&PlayerPeerId>)>,
     player_id: Res<PlayerId>,
 ) {
     if *status == ServerState::Host {
         write_player_state.send_batch(player_query.iter().filter_map(
-            |(player, transform, player_peer_id)| {
+            |(player, stats, transform, player_peer_id)| {
                 Some(NetworkEvent::PlayerState(PlayerState {
                     id: player_peer_id.map(|p| p.0).unwrap_or(player_id.0?),
                     position: transform.translation,
                     rotation: transform.rotation,
+                    health: player.health,
+                    max_health: stats.get(Stat::MaxHealth),
                 }))
             },
         ));
@@ -77,12 +262,14 @@ fn net_write(
     // only write local player state
     if *status == ServerState::Client {
         if let Some(player_peer_id) = player_id.0 {
-            let player = player_query.iter().find(|(_, _, p)| p.is_none());
-            if let Some((_, transform, _)) = player {
+            let player = player_query.iter().find(|(_, _, _, p)| p.is_none());
+            if let Some((player, stats, transform, _)) = player {
                 write_player_state.send(NetworkEvent::PlayerState(PlayerState {
                     id: player_peer_id,
                     position: transform.translation,
                     rotation: transform.rotation,
+                    health: player.health,
+                    max_health: stats.get(Stat::MaxHealth),
                 }));
             }
         }
@@ -91,27 +278,39 @@ fn net_write(
 
 fn net_read(
     mut read_player_state: EventReader<PlayerState>,
-    mut player_query: Query<(&mut Transform, &PlayerPeerId)>,
+    mut player_query: Query<(
+        &mut Transform,
+        &mut Interpolated,
+        &mut TeammateHealth,
+        &PlayerPeerId,
+    )>,
 ) {
     for player_state in read_player_state.read() {
-        for (mut transform, player_peer_id) in player_query.iter_mut() {
+        for (mut transform, mut interpolated, mut health, player_peer_id) in player_query.iter_mut()
+        {
             if player_peer_id.0 == player_state.id {
                 transform.translation = player_state.position;
                 transform.rotation = player_state.rotation;
+                interpolated.snap(&transform);
+                health.health = player_state.health;
+                health.max_health = player_state.max_health;
             }
         }
     }
 }
 
-fn update_transforms(time: Res<Time>, mut ships: Query<(&mut Ship, &mut Transform)>) {
+pub fn update_transforms(time: Res<Time>, mut ships: Query<(&mut Ship, &mut Transform)>) {
     let dt = time.delta_seconds();
     for (mut ship, mut transform) in &mut ships {
         transform.translation =
             transform.translation + ship.velocity * dt + 0.5 * ship.acceleration * dt * dt;
         let mut new_acceleration = ship.move_dir.clamp_length_max(1.0) * ship.acceleration_rate;
         new_acceleration -= ship.velocity * ship.drag_coefficient;
+        let speed_limit = ship
+            .max_speed
+            .max(ship.velocity.length() - KNOCKBACK_DECAY_RATE * dt);
         ship.velocity = (ship.velocity + 0.5 * (ship.acceleration + new_acceleration) * dt)
-            .clamp_length_max(ship.max_speed);
+            .clamp_length_max(speed_limit);
         ship.acceleration = new_acceleration;
 
         let look_dir = ship.look_dir.normalize_or_zero();
@@ -125,3 +324,37 @@ fn update_transforms(time: Res<Time>, mut ships: Query<(&mut Ship, &mut Transfor
         }
     }
 }
+
+fn resolve_collisions(mut ships: Query<(&mut Ship, &mut Transform, &Visibility)>) {
+    let mut combinations = ships.iter_combinations_mut();
+    while let Some([(mut a, mut a_transform, a_vis), (mut b, mut b_transform, b_vis)]) =
+        combinations.fetch_next()
+    {
+        if a_vis == Visibility::Hidden || b_vis == Visibility::Hidden {
+            continue;
+        }
+
+        let offset = (b_transform.translation - a_transform.translation) * Vec3::new(1.0, 0.0, 1.0);
+        let distance = offset.length();
+        let overlap = a.radius + b.radius - distance;
+        if overlap <= 0.0 {
+            continue;
+        }
+
+        let normal = if distance > 0.0 {
+            offset / distance
+        } else {
+            Vec3::X
+        };
+
+        // push both ships apart equally and cancel the velocity pulling them together
+        a_transform.translation -= normal * overlap * 0.5;
+        b_transform.translation += normal * overlap * 0.5;
+
+        let closing_speed = (a.velocity - b.velocity).dot(normal);
+        if closing_speed > 0.0 {
+            a.velocity -= normal * closing_speed * 0.5;
+            b.velocity += normal * closing_speed * 0.5;
+        }
+    }
+}
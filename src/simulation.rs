@@ -0,0 +1,217 @@
+use bevy::{ecs::schedule::ExecutorKind, prelude::*, transform::TransformSystem, utils::HashSet};
+
+use crate::net::packet::Handshake;
+
+#[derive(Debug, Clone)]
+pub struct SimulationPlugin {
+    pub tick_rate: f64,
+    pub seed: u64,
+}
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(self.tick_rate))
+            .insert_resource(Seed(self.seed))
+            .insert_resource(GameRng::new(self.seed))
+            .insert_resource(Paused::default())
+            .configure_sets(
+                FixedUpdate,
+                (
+                    SimulationSet::Restore,
+                    SimulationSet::Update,
+                    SimulationSet::Store,
+                )
+                    .chain(),
+            )
+            // virtual time only stops from the next frame on, this also holds the ticks of the
+            // frame a level up happens in. Menus pause from outside the simulation and replays
+            // restore them a frame early, so they only go through virtual time
+            .configure_sets(FixedUpdate, SimulationSet::Update.run_if(not_leveling_up))
+            .configure_sets(
+                PostUpdate,
+                SimulationSet::Interpolate.before(TransformSystem::TransformPropagate),
+            )
+            // the multithreaded executor picks a different order for ambiguous systems from run to
+            // run, which would make ticks unreproducible
+            .edit_schedule(FixedUpdate, |schedule| {
+                schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+            })
+            .add_systems(Startup, startup)
+            .add_systems(Update, handshake)
+            .add_systems(PostUpdate, pause)
+            .add_systems(
+                FixedUpdate,
+                (initialize, restore.after(initialize)).in_set(SimulationSet::Restore),
+            )
+            .add_systems(FixedUpdate, store.in_set(SimulationSet::Store))
+            .add_systems(
+                PostUpdate,
+                (initialize, interpolate.after(initialize)).in_set(SimulationSet::Interpolate),
+            );
+    }
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SimulationSet {
+    /// Puts simulated transforms back in place of the rendered ones
+    Restore,
+    /// Gameplay, runs once per tick
+    Update,
+    /// Records the simulated transforms at the end of a tick
+    Store,
+    /// Blends the last two ticks for rendering
+    Interpolate,
+}
+
+/// Why the simulation is held still
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PauseReason {
+    /// A player is choosing an upgrade
+    LevelUp,
+    /// A menu is open and nobody else is playing
+    Menu,
+}
+
+/// The simulation runs again once every reason to pause is gone
+#[derive(Debug, Default, Resource)]
+pub struct Paused(HashSet<PauseReason>);
+
+impl Paused {
+    pub fn set(&mut self, reason: PauseReason, paused: bool) {
+        if paused {
+            self.0.insert(reason);
+        } else {
+            self.0.remove(&reason);
+        }
+    }
+
+    pub fn contains(&self, reason: PauseReason) -> bool {
+        self.0.contains(&reason)
+    }
+}
+
+/// The seed the run was started with
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct Seed(pub u64);
+
+/// Randomness for anything that affects the simulation, never use global `fastrand` calls in
+/// gameplay code. Each use gets its own stream so that e.g. firing more bullets doesn't change
+/// where the next wave spawns
+#[derive(Debug, Clone, Resource)]
+pub struct GameRng {
+    pub spawn: fastrand::Rng,
+    pub loot: fastrand::Rng,
+    pub spread: fastrand::Rng,
+    pub upgrades: fastrand::Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        let mut root = fastrand::Rng::with_seed(seed);
+        Self {
+            spawn: root.fork(),
+            loot: root.fork(),
+            spread: root.fork(),
+            upgrades: root.fork(),
+        }
+    }
+}
+
+/// Transform state of the last two ticks, the `Transform` outside of `FixedUpdate` is a blend of
+/// the two
+#[derive(Clone, Component, Default)]
+pub struct Interpolated {
+    previous: Transform,
+    current: Transform,
+}
+
+impl Interpolated {
+    /// Moves without blending from the old position, use when teleporting or spawning from a pool
+    pub fn snap(&mut self, transform: &Transform) {
+        self.previous = *transform;
+        self.current = *transform;
+    }
+}
+
+fn startup(seed: Res<Seed>) {
+    info!(seed = seed.0, "simulation seeded");
+}
+
+/// Clients adopt the seed of the host they joined
+fn handshake(mut events: EventReader<Handshake>, mut seed: ResMut<Seed>, mut rng: ResMut<GameRng>) {
+    for event in events.read() {
+        info!(seed = event.seed, "simulation seed received from host");
+        seed.0 = event.seed;
+        *rng = GameRng::new(event.seed);
+    }
+}
+
+fn pause(paused: Res<Paused>, mut time: ResMut<Time<Virtual>>) {
+    let paused = !paused.0.is_empty();
+    if paused && !time.is_paused() {
+        time.pause();
+    } else if !paused && time.is_paused() {
+        time.unpause();
+    }
+}
+
+fn not_leveling_up(paused: Res<Paused>) -> bool {
+    !paused.contains(PauseReason::LevelUp)
+}
+
+fn initialize(mut query: Query<(&Transform, &mut Interpolated), Added<Interpolated>>) {
+    for (transform, mut interpolated) in &mut query {
+        interpolated.snap(transform);
+    }
+}
+
+fn restore(mut query: Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in &mut query {
+        *transform = interpolated.current;
+        interpolated.previous = interpolated.current;
+    }
+}
+
+fn store(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in &mut query {
+        interpolated.current = *transform;
+    }
+}
+
+fn interpolate(fixed_time: Res<Time<Fixed>>, mut query: Query<(&mut Transform, &Interpolated)>) {
+    let alpha = fixed_time.overstep_percentage();
+    for (mut transform, interpolated) in &mut query {
+        transform.translation = interpolated
+            .previous
+            .translation
+            .lerp(interpolated.current.translation, alpha);
+        transform.rotation = interpolated
+            .previous
+            .rotation
+            .slerp(interpolated.current.rotation, alpha);
+        transform.scale = interpolated.current.scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GameRng;
+
+    #[test]
+    fn test_streams_are_independent() {
+        let mut a = GameRng::new(42);
+        let mut b = GameRng::new(42);
+
+        // drawing from one stream must not shift the others
+        for _ in 0..100 {
+            a.spread.f32();
+        }
+
+        assert_eq!(a.spawn.u64(..), b.spawn.u64(..));
+        assert_eq!(a.loot.u64(..), b.loot.u64(..));
+        assert_ne!(
+            GameRng::new(42).spawn.u64(..),
+            GameRng::new(43).spawn.u64(..)
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    player::Player,
+    ship::{update_transforms, Ship},
+    simulation::SimulationSet,
+};
+
+/// Ticks timed modifiers and pushes the resulting values onto the components they drive
+#[derive(Debug)]
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            update
+                .before(update_transforms)
+                .in_set(SimulationSet::Update),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Stat {
+    MaxHealth,
+    MaxSpeed,
+    Acceleration,
+    /// Multiplies weapon damage
+    Damage,
+    /// Divides weapon cooldowns
+    FireRate,
+    PickupRadius,
+}
+
+impl Stat {
+    /// Stats that can be picked at a level up
+    pub const UPGRADES: [Stat; 5] = [
+        Stat::MaxHealth,
+        Stat::MaxSpeed,
+        Stat::Damage,
+        Stat::FireRate,
+        Stat::PickupRadius,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stat::MaxHealth => "max health",
+            Stat::MaxSpeed => "speed",
+            Stat::Acceleration => "acceleration",
+            Stat::Damage => "damage",
+            Stat::FireRate => "fire rate",
+            Stat::PickupRadius => "pickup radius",
+        }
+    }
+
+    /// What picking this stat at a level up gives
+    pub fn upgrade(self) -> Modifier {
+        match self {
+            Stat::MaxHealth => Modifier::Add(25.0),
+            Stat::PickupRadius => Modifier::Multiply(1.25),
+            _ => Modifier::Multiply(1.1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Modifier {
+    /// Added to the base value
+    Add(f32),
+    /// Scales the base value plus all additions
+    Multiply(f32),
+}
+
+impl Modifier {
+    pub fn label(self, stat: Stat) -> String {
+        match self {
+            Modifier::Add(value) => format!("{value:+.0} {}", stat.label()),
+            Modifier::Multiply(value) => format!("{:+.0}% {}", (value - 1.0) * 100.0, stat.label()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatModifier {
+    pub stat: Stat,
+    pub modifier: Modifier,
+    /// Seconds until the modifier expires, `None` lasts forever
+    pub duration: Option<f32>,
+}
+
+impl StatModifier {
+    pub fn permanent(stat: Stat, modifier: Modifier) -> Self {
+        Self {
+            stat,
+            modifier,
+            duration: None,
+        }
+    }
+
+    pub fn timed(stat: Stat, modifier: Modifier, duration: f32) -> Self {
+        Self {
+            stat,
+            modifier,
+            duration: Some(duration),
+        }
+    }
+}
+
+/// Base values and the modifiers stacked on top of them. Gameplay code reads stats from here
+/// instead of the components they end up driving
+#[derive(Component, Debug, Clone, Default)]
+pub struct Stats {
+    base: HashMap<Stat, f32>,
+    modifiers: Vec<StatModifier>,
+}
+
+impl Stats {
+    pub fn new(base: impl IntoIterator<Item = (Stat, f32)>) -> Self {
+        Self {
+            base: base.into_iter().collect(),
+            modifiers: Vec::new(),
+        }
+    }
+
+    /// Base value with every addition applied before every multiplier
+    pub fn get(&self, stat: Stat) -> f32 {
+        let mut added = self.base.get(&stat).copied().unwrap_or_default();
+        let mut multiplier = 1.0;
+        for modifier in self.modifiers.iter().filter(|m| m.stat == stat) {
+            match modifier.modifier {
+                Modifier::Add(value) => added += value,
+                Modifier::Multiply(value) => multiplier *= value,
+            }
+        }
+        added * multiplier
+    }
+
+    pub fn add(&mut self, modifier: StatModifier) {
+        self.modifiers.push(modifier);
+    }
+
+    /// Adds a timed modifier, or restarts it when an identical one is still running
+    pub fn refresh(&mut self, modifier: StatModifier) {
+        let running = self.modifiers.iter_mut().find(|m| {
+            m.stat == modifier.stat && m.modifier == modifier.modifier && m.duration.is_some()
+        });
+        match running {
+            Some(running) => running.duration = modifier.duration,
+            None => self.add(modifier),
+        }
+    }
+
+    /// Counts down timed modifiers and drops the expired ones
+    pub fn tick(&mut self, dt: f32) {
+        self.modifiers
+            .retain_mut(|modifier| match &mut modifier.duration {
+                Some(duration) => {
+                    *duration -= dt;
+                    *duration > 0.0
+                }
+                None => true,
+            });
+    }
+}
+
+fn update(time: Res<Time>, mut query: Query<(&mut Stats, Option<&mut Ship>, Option<&mut Player>)>) {
+    for (mut stats, ship, player) in &mut query {
+        stats.tick(time.delta_seconds());
+
+        if let Some(mut ship) = ship {
+            ship.max_speed = stats.get(Stat::MaxSpeed);
+            ship.acceleration_rate = stats.get(Stat::Acceleration);
+        }
+
+        if let Some(mut player) = player {
+            player.health = player.health.min(stats.get(Stat::MaxHealth));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Modifier, Stat, StatModifier, Stats};
+
+    #[test]
+    fn test_modifiers_stack_and_expire() {
+        let mut stats = Stats::new([(Stat::MaxSpeed, 10.0)]);
+        stats.add(StatModifier::permanent(Stat::MaxSpeed, Modifier::Add(2.0)));
+        stats.add(StatModifier::timed(
+            Stat::MaxSpeed,
+            Modifier::Multiply(1.5),
+            1.0,
+        ));
+        assert_eq!(stats.get(Stat::MaxSpeed), 18.0);
+        assert_eq!(stats.get(Stat::Damage), 0.0);
+
+        stats.tick(0.5);
+        assert_eq!(stats.get(Stat::MaxSpeed), 18.0);
+        stats.tick(0.5);
+        assert_eq!(stats.get(Stat::MaxSpeed), 12.0);
+    }
+}
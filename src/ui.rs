@@ -1,70 +1,1647 @@
-use bevy::prelude::*;
+use std::{env, io, process::Command};
 
-use crate::{enemy::SpawnGeneration, net::PlayerPeerId, player::Player};
+use bevy::{app::AppExit, ecs::system::SystemParam, math::vec2, prelude::*, utils::HashSet};
+use bevy_matchbox::matchbox_socket::PeerId;
 
-pub struct UiPlugin;
+use crate::{
+    buff::Buffs,
+    camera::{world_to_window, world_to_window_any, CameraController},
+    enemy::{Enemy, Kills, SpawnGeneration},
+    experience::{ChoosingPeers, LevelUp},
+    input::{Action, ActionState, Binding, Bindings},
+    net::replay::{Playback, Recorder},
+    net::{PlayerPeerId, StartGame},
+    pickup::Pickup,
+    player::{
+        AddLocalPlayer, FireMode, LocalPlayer, Player, PlayerName, PrimaryPlayer, TeammateHealth,
+        MAX_LOCAL_PLAYERS, MAX_NAME_LENGTH,
+    },
+    powerups::Powerup,
+    save::{Save, Shop, SHOP_KEYS},
+    scoreboard::{ExportScoreboard, RunStats},
+    settings::Settings,
+    ship::ShipType,
+    simulation::{PauseReason, Paused},
+    stats::{Stat, Stats},
+    weapon::Weapons,
+};
+
+/// The HUD and the menus
+pub struct UiPlugin {
+    /// Starts at the main menu, replays go straight into the game
+    pub main_menu: bool,
+}
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, startup)
-            .add_systems(Update, update);
+        if self.main_menu {
+            app.insert_resource(State::new(MenuState::Main));
+        } else {
+            app.insert_resource(State::new(MenuState::Closed))
+                .insert_resource(Playing { online: false });
+        }
+
+        app.add_state::<MenuState>()
+            .add_event::<FeedEvent>()
+            .insert_resource(MenuFocus::default())
+            .insert_resource(RoomInput::default())
+            .insert_resource(NameInput::default())
+            .insert_resource(Rebinding::default())
+            .insert_resource(Joining::default())
+            .add_systems(Startup, startup)
+            .add_systems(OnEnter(MenuState::Name), edit_name)
+            .add_systems(OnExit(MenuState::Name), commit_name)
+            .add_systems(
+                Update,
+                (
+                    update,
+                    update_teammates,
+                    update_minimap,
+                    update_indicators,
+                    update_nameplates,
+                    update_level_up,
+                    update_buffs,
+                    update_shop,
+                    teammate_deaths,
+                    update_feed.after(teammate_deaths),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    type_room.run_if(in_state(MenuState::JoinRoom)),
+                    type_name.run_if(in_state(MenuState::Name)),
+                    navigate_menu.after(type_room).after(type_name),
+                    rebind.after(navigate_menu),
+                    join.after(navigate_menu),
+                    spawn_menu.after(rebind).after(join),
+                    highlight_menu.after(spawn_menu),
+                    pause_menu.run_if(not(resource_exists::<Playback>())),
+                ),
+            );
+    }
+}
+
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MenuState {
+    #[default]
+    Main,
+    JoinRoom,
+    Settings,
+    /// Typing a new name, reached from the settings
+    Name,
+    /// Rebinding actions, reached from the settings
+    Controls,
+    /// Only pauses the game when playing offline
+    Paused,
+    /// The scoreboard, shown once the local ship runs out of health
+    GameOver,
+    Closed,
+}
+
+impl MenuState {
+    /// Where escape or the gamepad's back button leads
+    fn back(self, playing: bool) -> Self {
+        match self {
+            MenuState::Main | MenuState::JoinRoom => MenuState::Main,
+            MenuState::Settings if playing => MenuState::Paused,
+            MenuState::Settings => MenuState::Main,
+            MenuState::Name | MenuState::Controls => MenuState::Settings,
+            MenuState::Paused => MenuState::Closed,
+            MenuState::GameOver => MenuState::GameOver,
+            MenuState::Closed => MenuState::Paused,
+        }
+    }
+}
+
+/// Present once the game started
+#[derive(Resource)]
+struct Playing {
+    online: bool,
+}
+
+/// Index of the highlighted menu button
+#[derive(Debug, Default, Resource)]
+struct MenuFocus(usize);
+
+/// Room name typed on the join room screen
+#[derive(Debug, Default, Resource)]
+struct RoomInput(String);
+
+/// Name being typed on the name screen
+#[derive(Debug, Default, Resource)]
+struct NameInput(String);
+
+/// The action waiting for a key or button on the controls screen
+#[derive(Debug, Default, Resource)]
+struct Rebinding {
+    action: Option<Action>,
+    /// Actions the last rebind took a key or button from, and which one
+    taken: Vec<(Action, Binding)>,
+}
+
+/// Waiting for a button on the gamepad of a player joining the couch co-op
+#[derive(Debug, Default, Resource)]
+struct Joining(bool);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuAction {
+    PlayOnline,
+    JoinRoom,
+    Join,
+    PlayOffline,
+    Ship,
+    Settings,
+    Name,
+    Volume,
+    Fullscreen,
+    Vsync,
+    SharedCamera,
+    FireMode,
+    Controls,
+    Rebind(Action),
+    ResetControls,
+    Resume,
+    AddPlayer,
+    Export,
+    MainMenu,
+    Back,
+    Quit,
+}
+
+impl MenuAction {
+    fn label(self, settings: &Settings) -> String {
+        let on_off = |value: bool| if value { "On" } else { "Off" };
+        match self {
+            MenuAction::PlayOnline => "Play online".to_string(),
+            MenuAction::JoinRoom => "Join room".to_string(),
+            MenuAction::Join => "Join".to_string(),
+            MenuAction::PlayOffline => "Play offline".to_string(),
+            MenuAction::Ship => format!("Ship: {}", settings.ship().label()),
+            MenuAction::Settings => "Settings".to_string(),
+            MenuAction::Name => format!("Name: {}", settings.name()),
+            MenuAction::Volume => format!("Volume: {:.0}%", settings.volume * 100.0),
+            MenuAction::Fullscreen => format!("Fullscreen: {}", on_off(settings.fullscreen)),
+            MenuAction::Vsync => format!("VSync: {}", on_off(settings.vsync)),
+            MenuAction::SharedCamera => {
+                format!("Shared camera: {}", on_off(settings.shared_camera))
+            }
+            MenuAction::FireMode => format!("Fire mode: {}", settings.fire_mode.label()),
+            MenuAction::Controls => "Controls".to_string(),
+            MenuAction::Rebind(action) => {
+                format!("{}: {}", action.label(), settings.bindings.label(action))
+            }
+            MenuAction::ResetControls => "Reset to defaults".to_string(),
+            MenuAction::Resume => "Resume".to_string(),
+            // the keyboard and mouse stay with the first player
+            MenuAction::AddPlayer => "Add gamepad player".to_string(),
+            MenuAction::Export => "Export JSON".to_string(),
+            MenuAction::MainMenu => "Main menu".to_string(),
+            MenuAction::Back => "Back".to_string(),
+            MenuAction::Quit => "Quit".to_string(),
+        }
     }
 }
 
+/// Listed on the controls screen, these can't be rebound
+const FIXED_CONTROLS: [&str; 2] = [
+    "Aim: also the mouse, right stick or touch the right half of the screen",
+    "Move by touch: drag on the left half of the screen",
+];
+
+/// Loaded once at startup for text spawned later on
+#[derive(Resource)]
+struct UiFont(Handle<Font>);
+
+#[derive(Component)]
+struct MenuRoot;
+
+#[derive(Component)]
+struct MenuButton {
+    index: usize,
+    action: MenuAction,
+}
+
+/// Something worth a line in the event feed
+#[derive(Event, Debug, Clone)]
+pub enum FeedEvent {
+    /// The local player reached the level
+    LevelUp(u32),
+    TeammateLevelUp(PeerId),
+    TeammateDied(PeerId),
+}
+
+impl FeedEvent {
+    fn label(&self, name: impl Fn(PeerId) -> String) -> String {
+        match self {
+            FeedEvent::LevelUp(level) => format!("Reached level {level}"),
+            FeedEvent::TeammateLevelUp(peer_id) => format!("{} leveled up", name(*peer_id)),
+            FeedEvent::TeammateDied(peer_id) => format!("{} died", name(*peer_id)),
+        }
+    }
+}
+
+const FEED_LINES: usize = 5;
+/// Seconds a line stays in the feed
+const FEED_DURATION: f32 = 5.0;
+
+const NAMEPLATE_SIZE: Vec2 = Vec2::new(200.0, 24.0);
+/// Pixels between the ship's center and the bottom of its nameplate
+const NAMEPLATE_OFFSET: f32 = 20.0;
+
+const MAX_INDICATORS: usize = 16;
+const INDICATOR_SIZE: Vec2 = Vec2::new(140.0, 56.0);
+
+/// How far the left stick has to be pushed to move the menu focus, and let go to move it again
+const MENU_STICK_PUSH: f32 = 0.6;
+const MENU_STICK_RELEASE: f32 = 0.3;
+/// Menu buttons in a column before the rest go in the next one
+const MENU_COLUMN_LENGTH: usize = 10;
+
+const MINIMAP_SIZE: f32 = 160.0;
+/// World units from the local ship to the edge of the minimap
+const MINIMAP_RANGE: f32 = 30.0;
+const MINIMAP_DOT_SIZE: f32 = 4.0;
+const MAX_MINIMAP_DOTS: usize = 256;
+
+#[derive(Component)]
+enum HudText {
+    Health,
+    Level,
+    /// Wave, kills, survival time and fire mode
+    Status,
+}
+
+/// The fill of a bar, its width is the fraction filled
+#[derive(Component)]
+enum HudBar {
+    Health,
+    Experience,
+}
+
+#[derive(Component)]
+struct TeammateList;
+
+/// Health chip of the remote player entity
+#[derive(Component)]
+struct TeammateChip(Entity);
+
+#[derive(Component)]
+struct TeammateBar(Entity);
+
+#[derive(Component)]
+struct TeammateName(Entity);
+
+/// Name floating over the remote player entity's ship
+#[derive(Component)]
+struct Nameplate(Entity);
+
+#[derive(Component)]
+struct Minimap;
+
+#[derive(Component)]
+struct MinimapDot;
+
+/// Edge of the screen arrow pointing at something off-screen, free while hidden
+#[derive(Component)]
+struct Indicator;
+
+#[derive(Component)]
+struct IndicatorArrow;
+
+#[derive(Component)]
+struct IndicatorText;
+
+#[derive(Component)]
+struct FeedList;
+
+/// Seconds left before the line is removed
+#[derive(Component)]
+struct FeedLine(f32);
+
 #[derive(Component)]
-struct UiText;
+struct LevelUpText;
+
+#[derive(Component)]
+struct BuffText;
+
+#[derive(Component)]
+struct ShopText;
 
 fn startup(mut commands: Commands, server: Res<AssetServer>) {
     let font = server.load("fonts/Roboto-Regular.ttf");
+    commands.insert_resource(UiFont(font.clone()));
 
-    commands.spawn((
-        TextBundle::from_sections([
-            TextSection::new(
-                "Wave: ",
-                TextStyle {
-                    font_size: 50.0,
-                    font: font.clone(),
+    let text_style = TextStyle {
+        font_size: 30.0,
+        font: font.clone(),
+        ..default()
+    };
+    let row = NodeBundle {
+        style: Style {
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(8.0),
+            ..default()
+        },
+        ..default()
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(5.0),
+                left: Val::Px(5.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(row.clone()).with_children(|parent| {
+                spawn_bar(parent, vec2(300.0, 24.0), Color::RED, HudBar::Health);
+                parent.spawn((
+                    TextBundle::from_section("", text_style.clone()),
+                    HudText::Health,
+                ));
+            });
+            parent.spawn(row).with_children(|parent| {
+                spawn_bar(parent, vec2(300.0, 12.0), Color::GREEN, HudBar::Experience);
+                parent.spawn((
+                    TextBundle::from_section("", text_style.clone()),
+                    HudText::Level,
+                ));
+            });
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                HudText::Status,
+            ));
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(2.0),
+                        ..default()
+                    },
                     ..default()
                 },
-            ),
-            TextSection::from_style(TextStyle {
-                font_size: 50.0,
-                font: font.clone(),
+                TeammateList,
+            ));
+        });
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(5.0),
+                    right: Val::Px(5.0),
+                    width: Val::Px(MINIMAP_SIZE),
+                    height: Val::Px(MINIMAP_SIZE),
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
                 ..default()
-            }),
-            TextSection::new(
-                "\nHealth: ",
-                TextStyle {
-                    font_size: 50.0,
-                    font: font.clone(),
+            },
+            Minimap,
+        ))
+        .with_children(|parent| {
+            for _ in 0..MAX_MINIMAP_DOTS {
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            width: Val::Px(MINIMAP_DOT_SIZE),
+                            height: Val::Px(MINIMAP_DOT_SIZE),
+                            display: Display::None,
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    MinimapDot,
+                ));
+            }
+        });
+
+    for _ in 0..MAX_INDICATORS {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Px(INDICATOR_SIZE.x),
+                        height: Val::Px(INDICATOR_SIZE.y),
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        display: Display::None,
+                        ..default()
+                    },
                     ..default()
                 },
-            ),
-            TextSection::from_style(TextStyle {
-                font_size: 50.0,
+                Indicator,
+            ))
+            .with_children(|parent| {
+                // pointing right until rotated
+                parent.spawn((
+                    TextBundle::from_section(
+                        ">",
+                        TextStyle {
+                            font_size: 30.0,
+                            font: font.clone(),
+                            ..default()
+                        },
+                    ),
+                    IndicatorArrow,
+                ));
+                parent.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 18.0,
+                            font: font.clone(),
+                            ..default()
+                        },
+                    ),
+                    IndicatorText,
+                ));
+            });
+    }
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(MINIMAP_SIZE + 10.0),
+                right: Val::Px(5.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::FlexEnd,
+                ..default()
+            },
+            ..default()
+        },
+        FeedList,
+    ));
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 40.0,
                 font: font.clone(),
                 ..default()
-            }),
-        ])
+            },
+        )
+        .with_text_alignment(TextAlignment::Center)
         .with_style(Style {
-            align_self: AlignSelf::FlexStart,
             position_type: PositionType::Absolute,
-            top: Val::Px(5.0),
+            align_self: AlignSelf::Center,
+            justify_self: JustifySelf::Center,
+            ..default()
+        }),
+        LevelUpText,
+    ));
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 30.0,
+                font: font.clone(),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(5.0),
             left: Val::Px(5.0),
             ..default()
         }),
-        UiText,
+        BuffText,
     ));
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 30.0,
+                font: font.clone(),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            right: Val::Px(5.0),
+            ..default()
+        }),
+        ShopText,
+    ));
+}
+
+fn spawn_bar(parent: &mut ChildBuilder, size: Vec2, color: Color, fill: impl Bundle) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(size.x),
+                height: Val::Px(size.y),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    background_color: color.into(),
+                    ..default()
+                },
+                fill,
+            ));
+        });
+}
+
+fn fraction(value: f32, max: f32) -> Val {
+    Val::Percent((value / max).clamp(0.0, 1.0) * 100.0)
 }
 
 fn update(
-    mut ui_text: Query<&mut Text, With<UiText>>,
+    mut texts: Query<(&mut Text, &HudText)>,
+    mut bars: Query<(&mut Style, &HudBar)>,
     spawn_generation: Res<SpawnGeneration>,
-    player: Query<&Player, Without<PlayerPeerId>>,
+    kills: Res<Kills>,
+    time: Res<Time<Virtual>>,
+    player: Query<(&Player, &Stats, &FireMode), With<PrimaryPlayer>>,
+) {
+    let (player, stats, fire_mode) = player.single();
+    let max_health = stats.get(Stat::MaxHealth);
+
+    for (mut style, bar) in &mut bars {
+        style.width = match bar {
+            HudBar::Health => fraction(player.health, max_health),
+            HudBar::Experience => fraction(player.experience, player.experience_to_level_up()),
+        };
+    }
+
+    let seconds = time.elapsed_seconds() as u32;
+    for (mut text, hud_text) in &mut texts {
+        text.sections[0].value = match hud_text {
+            HudText::Health => format!("{:.0}/{:.0}", player.health, max_health),
+            HudText::Level => format!("Level {}", player.level),
+            HudText::Status => format!(
+                "Wave {}   Kills {}   {:02}:{:02}\nFire: {}",
+                spawn_generation.0,
+                kills.0,
+                seconds / 60,
+                seconds % 60,
+                fire_mode.label()
+            ),
+        };
+    }
+}
+
+/// Keeps one health chip per remote player and per local player after the first
+fn update_teammates(
+    mut commands: Commands,
+    font: Res<UiFont>,
+    list: Query<Entity, With<TeammateList>>,
+    teammates: Query<
+        (
+            Entity,
+            &PlayerName,
+            Option<&TeammateHealth>,
+            Option<(&Player, &Stats)>,
+        ),
+        Or<(
+            With<PlayerPeerId>,
+            (With<LocalPlayer>, Without<PrimaryPlayer>),
+        )>,
+    >,
+    chips: Query<(Entity, &TeammateChip)>,
+    mut bars: Query<(&mut Style, &TeammateBar)>,
+    mut names: Query<(&mut Text, &TeammateName)>,
 ) {
-    let player = player.single();
-    for mut text in ui_text.iter_mut() {
-        text.sections[1].value = spawn_generation.0.to_string();
-        text.sections[3].value = format!("{:.0}", player.health);
+    for (chip, TeammateChip(teammate)) in &chips {
+        if !teammates.contains(*teammate) {
+            commands.entity(chip).despawn_recursive();
+        }
+    }
+
+    let list = list.single();
+    for (teammate, ..) in &teammates {
+        if chips.iter().any(|(_, chip)| chip.0 == teammate) {
+            continue;
+        }
+        let chip = commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(6.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                TeammateChip(teammate),
+            ))
+            .with_children(|parent| {
+                spawn_bar(parent, vec2(100.0, 8.0), Color::RED, TeammateBar(teammate));
+                parent.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 20.0,
+                            font: font.0.clone(),
+                            ..default()
+                        },
+                    ),
+                    TeammateName(teammate),
+                ));
+            })
+            .id();
+        commands.entity(list).add_child(chip);
+    }
+
+    for (mut style, TeammateBar(teammate)) in &mut bars {
+        match teammates.get(*teammate) {
+            Ok((_, _, Some(health), _)) => {
+                style.width = fraction(health.health, health.max_health);
+            }
+            Ok((_, _, _, Some((player, stats)))) => {
+                style.width = fraction(player.health, stats.get(Stat::MaxHealth));
+            }
+            _ => {}
+        }
+    }
+    // names arrive after the peer connects, and can change
+    for (mut text, TeammateName(teammate)) in &mut names {
+        if let Ok((_, name, ..)) = teammates.get(*teammate) {
+            text.sections[0].value.clone_from(&name.0);
+        }
     }
 }
+
+/// Keeps a nameplate over every remote ship, and over every local one in couch co-op
+fn update_nameplates(
+    mut commands: Commands,
+    font: Res<UiFont>,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraController>>,
+    ships: Query<
+        (Entity, &GlobalTransform, &PlayerName, Has<LocalPlayer>),
+        Or<(With<PlayerPeerId>, With<LocalPlayer>)>,
+    >,
+    mut nameplates: Query<(Entity, &Nameplate, &mut Style, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    let couch = ships.iter().filter(|(.., local)| *local).count() > 1;
+    let teammates = |entity| ships.get(entity).ok().filter(|(.., local)| couch || !local);
+
+    for (nameplate, Nameplate(teammate), mut style, children) in &mut nameplates {
+        let Some((_, transform, name, _)) = teammates(*teammate) else {
+            commands.entity(nameplate).despawn_recursive();
+            continue;
+        };
+        let Some(position) = world_to_window_any(&cameras, transform.translation()) else {
+            style.display = Display::None;
+            continue;
+        };
+        style.display = Display::Flex;
+        style.left = Val::Px(position.x - NAMEPLATE_SIZE.x * 0.5);
+        style.top = Val::Px(position.y - NAMEPLATE_SIZE.y - NAMEPLATE_OFFSET);
+        for &child in children {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.sections[0].value.clone_from(&name.0);
+            }
+        }
+    }
+
+    for (teammate, ..) in &ships {
+        if teammates(teammate).is_none() {
+            continue;
+        }
+        if nameplates
+            .iter()
+            .any(|(_, nameplate, _, _)| nameplate.0 == teammate)
+        {
+            continue;
+        }
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Px(NAMEPLATE_SIZE.x),
+                        height: Val::Px(NAMEPLATE_SIZE.y),
+                        justify_content: JustifyContent::Center,
+                        display: Display::None,
+                        ..default()
+                    },
+                    ..default()
+                },
+                Nameplate(teammate),
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 18.0,
+                        font: font.0.clone(),
+                        color: Color::CYAN,
+                    },
+                ));
+            });
+    }
+}
+
+/// A teammate whose health ran out, back above zero they can die again
+fn teammate_deaths(
+    teammates: Query<(Entity, &PlayerPeerId, &TeammateHealth)>,
+    mut dead: Local<HashSet<Entity>>,
+    mut feed_events: EventWriter<FeedEvent>,
+) {
+    dead.retain(|teammate| teammates.contains(*teammate));
+    for (teammate, peer_id, health) in &teammates {
+        // nothing received yet
+        if health.max_health <= 0.0 {
+            continue;
+        }
+        if health.health > 0.0 {
+            dead.remove(&teammate);
+        } else if dead.insert(teammate) {
+            feed_events.send(FeedEvent::TeammateDied(peer_id.0));
+        }
+    }
+}
+
+/// Newest line at the bottom, lines expire after [`FEED_DURATION`]
+fn update_feed(
+    mut commands: Commands,
+    font: Res<UiFont>,
+    time: Res<Time>,
+    mut events: EventReader<FeedEvent>,
+    names: Query<(&PlayerPeerId, &PlayerName)>,
+    list: Query<(Entity, Option<&Children>), With<FeedList>>,
+    mut lines: Query<&mut FeedLine>,
+) {
+    let (list, children) = list.single();
+
+    let mut shown = Vec::new();
+    for &line in children.into_iter().flatten() {
+        let Ok(mut feed_line) = lines.get_mut(line) else {
+            continue;
+        };
+        feed_line.0 -= time.delta_seconds();
+        if feed_line.0 <= 0.0 {
+            commands.entity(line).despawn_recursive();
+        } else {
+            shown.push(line);
+        }
+    }
+
+    let name = |peer_id| {
+        names
+            .iter()
+            .find(|(id, _)| id.0 == peer_id)
+            .map_or_else(|| PlayerName::peer(peer_id).0, |(_, name)| name.0.clone())
+    };
+    for event in events.read() {
+        let line = commands
+            .spawn((
+                TextBundle::from_section(
+                    event.label(name),
+                    TextStyle {
+                        font_size: 24.0,
+                        font: font.0.clone(),
+                        ..default()
+                    },
+                ),
+                FeedLine(FEED_DURATION),
+            ))
+            .id();
+        commands.entity(list).add_child(line);
+        shown.push(line);
+    }
+
+    // drop the oldest when too many came in at once
+    let excess = shown.len().saturating_sub(FEED_LINES);
+    for line in shown.into_iter().take(excess) {
+        commands.entity(line).despawn_recursive();
+    }
+}
+
+/// Dots for everything within [`MINIMAP_RANGE`] of the local ship. Players take dots first so a
+/// crowded map drops pickups rather than them, and are layered on top
+fn update_minimap(
+    player: Query<&Transform, With<PrimaryPlayer>>,
+    teammates: Query<
+        &Transform,
+        Or<(
+            With<PlayerPeerId>,
+            (With<LocalPlayer>, Without<PrimaryPlayer>),
+        )>,
+    >,
+    enemies: Query<(&Transform, &Visibility), With<Enemy>>,
+    pickups: Query<(&Transform, &Visibility, Option<&Powerup>), With<Pickup>>,
+    mut dots: Query<(&mut Style, &mut BackgroundColor, &mut ZIndex), With<MinimapDot>>,
+) {
+    let player_position = player.single().translation;
+    let center = player_position.xz();
+    let visible = |visibility: &Visibility| *visibility != Visibility::Hidden;
+
+    let pickups = pickups
+        .iter()
+        .filter(|(_, visibility, _)| visible(visibility))
+        .map(|(transform, _, powerup)| {
+            let color = if powerup.is_some() {
+                Color::YELLOW
+            } else {
+                Color::GREEN
+            };
+            (transform.translation, color, 0)
+        });
+    let enemies = enemies
+        .iter()
+        .filter(|(_, visibility)| visible(visibility))
+        .map(|(transform, _)| (transform.translation, Color::RED, 1));
+    let teammates = teammates
+        .iter()
+        .map(|transform| (transform.translation, Color::CYAN, 2));
+    let local = std::iter::once((player_position, Color::WHITE, 3));
+
+    let mut points = local
+        .chain(teammates)
+        .chain(enemies)
+        .chain(pickups)
+        .filter_map(|(position, color, layer)| {
+            let offset = (position.xz() - center) / MINIMAP_RANGE;
+            (offset.abs().max_element() <= 1.0).then_some((offset, color, layer))
+        });
+
+    for (mut style, mut background, mut z_index) in &mut dots {
+        let Some((offset, color, layer)) = points.next() else {
+            style.display = Display::None;
+            continue;
+        };
+        let position = (offset * 0.5 + 0.5) * MINIMAP_SIZE - MINIMAP_DOT_SIZE * 0.5;
+        style.display = Display::Flex;
+        style.left = Val::Px(position.x);
+        style.top = Val::Px(position.y);
+        *background = color.into();
+        *z_index = ZIndex::Local(layer);
+    }
+}
+
+/// Arrows along the screen edge for teammates, bosses and powerups out of view, labeled with
+/// their distance from the local ship
+fn update_indicators(
+    cameras: Query<(&Camera, &GlobalTransform, &CameraController)>,
+    player: Query<&GlobalTransform, With<PrimaryPlayer>>,
+    teammates: Query<(&GlobalTransform, &PlayerName), With<PlayerPeerId>>,
+    enemies: Query<(&GlobalTransform, &Visibility, &Enemy)>,
+    powerups: Query<(&GlobalTransform, &Visibility, &Powerup)>,
+    mut indicators: Query<(&mut Style, &Children), With<Indicator>>,
+    mut arrows: Query<(&mut Transform, &mut Text), With<IndicatorArrow>>,
+    mut texts: Query<&mut Text, (With<IndicatorText>, Without<IndicatorArrow>)>,
+) {
+    // around the first player's view when the window is split
+    let Some((camera, camera_transform, _)) = cameras
+        .iter()
+        .find(|(_, _, controller)| controller.seat == 0)
+    else {
+        return;
+    };
+    let Some(viewport) = camera.logical_viewport_rect() else {
+        return;
+    };
+    let viewport_size = viewport.size();
+    let player_position = player.single().translation();
+    let visible = |visibility: &Visibility| *visibility != Visibility::Hidden;
+
+    let teammates = teammates
+        .iter()
+        .map(|(transform, name)| (transform.translation(), name.0.clone(), Color::CYAN));
+    let bosses = enemies
+        .iter()
+        .filter(|(_, visibility, enemy)| visible(visibility) && enemy.boss)
+        .map(|(transform, _, _)| (transform.translation(), "Boss".to_string(), Color::RED));
+    let powerups = powerups
+        .iter()
+        .filter(|(_, visibility, _)| visible(visibility))
+        .map(|(transform, _, powerup)| {
+            let label = powerup.powerup_type.label().to_string();
+            (transform.translation(), label, Color::YELLOW)
+        });
+
+    let center = viewport_size * 0.5;
+    let bounds = center - INDICATOR_SIZE * 0.5;
+    let mut targets =
+        teammates
+            .chain(bosses)
+            .chain(powerups)
+            .filter_map(|(position, label, color)| {
+                let window_position = world_to_window(camera, camera_transform, position)?;
+                let offset = window_position - viewport.min - center;
+                // on screen, no arrow needed
+                if offset.abs().cmple(center).all() {
+                    return None;
+                }
+                let distance = position.distance(player_position);
+                Some((offset, format!("{label} {distance:.0}m"), color))
+            });
+
+    for (mut style, children) in &mut indicators {
+        let Some((offset, label, color)) = targets.next() else {
+            style.display = Display::None;
+            continue;
+        };
+        // slide the offset back along itself until the indicator fits on screen
+        let scale = (bounds / offset.abs()).min_element();
+        let position = viewport.min + center + offset * scale - INDICATOR_SIZE * 0.5;
+        style.display = Display::Flex;
+        style.left = Val::Px(position.x);
+        style.top = Val::Px(position.y);
+
+        for &child in children {
+            if let Ok((mut transform, mut text)) = arrows.get_mut(child) {
+                // ui y points down, so this turns clockwise like the offset does
+                transform.rotation = Quat::from_rotation_z(offset.y.atan2(offset.x));
+                text.sections[0].style.color = color;
+            }
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.sections[0].value = label.clone();
+                text.sections[0].style.color = color;
+            }
+        }
+    }
+}
+
+fn update_level_up(
+    mut level_up_text: Query<&mut Text, With<LevelUpText>>,
+    level_up: Option<Res<LevelUp>>,
+    choosing: Res<ChoosingPeers>,
+    settings: Res<Settings>,
+    players: Query<(&Weapons, &PlayerName, &LocalPlayer)>,
+) {
+    for mut text in level_up_text.iter_mut() {
+        let choosing_player = level_up
+            .as_ref()
+            .and_then(|level_up| players.get(level_up.player).ok());
+        text.sections[0].value =
+            if let (Some(level_up), Some((weapons, name, local))) = (&level_up, choosing_player) {
+                // in couch co-op, whoever's turn it is
+                let mut value = if players.iter().count() > 1 {
+                    format!("{} levels up!", name.0)
+                } else {
+                    "Level up!".to_string()
+                };
+                for (upgrade, action) in level_up.choices.iter().zip(Action::UPGRADES) {
+                    // only the buttons on the device they're playing with
+                    let keys = settings
+                        .bindings
+                        .get(action)
+                        .into_iter()
+                        .filter(|binding| binding.gamepad() == local.gamepad.is_some())
+                        .map(Binding::label)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    value += &format!("\n{keys}: {}", upgrade.label(weapons));
+                }
+                value
+            } else if !choosing.0.is_empty() {
+                "Waiting for other players to choose".to_string()
+            } else {
+                String::new()
+            };
+    }
+}
+
+fn update_buffs(
+    mut buff_text: Query<&mut Text, With<BuffText>>,
+    buffs: Query<&Buffs, With<PrimaryPlayer>>,
+) {
+    let buffs = buffs.single();
+    for mut text in buff_text.iter_mut() {
+        text.sections[0].value = buffs
+            .iter()
+            .map(|active| format!("{} {:.0}s", active.buff.label(), active.remaining.ceil()))
+            .collect::<Vec<_>>()
+            .join("   ");
+    }
+}
+
+fn update_shop(
+    mut shop_text: Query<&mut Text, With<ShopText>>,
+    shop: Option<Res<Shop>>,
+    save: Res<Save>,
+    settings: Res<Settings>,
+) {
+    for mut text in shop_text.iter_mut() {
+        text.sections[0].value = if shop.is_some() {
+            let mut value = format!(
+                "Unlocks ({} credits)\nBest level {}, {:.0} minutes played",
+                save.currency,
+                save.best_level(),
+                save.seconds_played() / 60.0
+            );
+            for (i, unlock) in save.for_sale().enumerate() {
+                value += &format!(
+                    "\n{}. {} ({})",
+                    SHOP_KEYS[i].1,
+                    unlock.label(),
+                    unlock.cost()
+                );
+            }
+            value
+        } else {
+            format!("{}: unlocks", settings.bindings.label(Action::Interact))
+        };
+    }
+}
+
+fn edit_name(settings: Res<Settings>, mut name: ResMut<NameInput>) {
+    name.0 = settings.name().to_string();
+}
+
+fn type_name(
+    keys: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut name: ResMut<NameInput>,
+) {
+    for event in characters.read() {
+        let allowed = event.char.is_alphanumeric() || " -_".contains(event.char);
+        if allowed && name.0.chars().count() < MAX_NAME_LENGTH {
+            name.0.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        name.0.pop();
+    }
+}
+
+/// The typed name is only saved and sent to other players once the name screen is left
+fn commit_name(name: Res<NameInput>, mut settings: ResMut<Settings>) {
+    if name.0 != settings.name() {
+        settings.name = name.0.clone();
+        settings.name_override = None;
+    }
+}
+
+fn type_room(
+    keys: Res<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut room: ResMut<RoomInput>,
+) {
+    for event in characters.read() {
+        if (event.char.is_ascii_alphanumeric() || event.char == '-') && room.0.len() < 24 {
+            room.0.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        room.0.pop();
+    }
+}
+
+/// Keys, buttons and the left stick moving through menus, these can't be rebound
+#[derive(SystemParam)]
+struct MenuInput<'w, 's> {
+    keys: Res<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    gamepad_buttons: Res<'w, Input<GamepadButton>>,
+    gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+    /// The stick moves the focus once per push, it has to come back before moving it again
+    stick_pushed: Local<'s, bool>,
+}
+
+impl MenuInput<'_, '_> {
+    fn gamepad_pressed(&self, button_type: GamepadButtonType) -> bool {
+        self.gamepads.iter().any(|gamepad| {
+            self.gamepad_buttons
+                .just_pressed(GamepadButton::new(gamepad, button_type))
+        })
+    }
+
+    /// -1 moves the focus up, 1 down, call once per frame
+    fn vertical(&mut self) -> i32 {
+        if self.keys.just_pressed(KeyCode::Up) || self.gamepad_pressed(GamepadButtonType::DPadUp) {
+            return -1;
+        }
+        if self.keys.just_pressed(KeyCode::Down)
+            || self.gamepad_pressed(GamepadButtonType::DPadDown)
+        {
+            return 1;
+        }
+
+        let stick = self
+            .gamepads
+            .iter()
+            .filter_map(|gamepad| {
+                self.gamepad_axes
+                    .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+            })
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or_default();
+        if stick.abs() < MENU_STICK_RELEASE {
+            *self.stick_pushed = false;
+        }
+        if *self.stick_pushed || stick.abs() < MENU_STICK_PUSH {
+            return 0;
+        }
+        *self.stick_pushed = true;
+        // the stick's y points up, the menu's down
+        if stick > 0.0 {
+            -1
+        } else {
+            1
+        }
+    }
+
+    fn confirm(&self) -> bool {
+        self.keys.just_pressed(KeyCode::Return) || self.gamepad_pressed(GamepadButtonType::South)
+    }
+}
+
+/// Events the menu buttons send
+#[derive(SystemParam)]
+struct MenuEvents<'w> {
+    start_game: EventWriter<'w, StartGame>,
+    export: EventWriter<'w, ExportScoreboard>,
+    exit: EventWriter<'w, AppExit>,
+}
+
+/// Moves the focus with arrow keys, the d-pad, the left stick or the mouse and runs the focused
+/// button's action
+fn navigate_menu(
+    mut commands: Commands,
+    mut input: MenuInput,
+    actions: Res<ActionState>,
+    mut rebinding: ResMut<Rebinding>,
+    mut joining: ResMut<Joining>,
+    state: Res<State<MenuState>>,
+    mut next_state: ResMut<NextState<MenuState>>,
+    playing: Option<Res<Playing>>,
+    mut focus: ResMut<MenuFocus>,
+    mut settings: ResMut<Settings>,
+    save: Res<Save>,
+    room: Res<RoomInput>,
+    buttons: Query<&MenuButton>,
+    interactions: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut events: MenuEvents,
+) {
+    // the next key or button goes to the action being rebound or the joining player
+    if rebinding.action.is_some() || joining.0 {
+        return;
+    }
+
+    // back only leaves menus, during play its buttons are free for other things like upgrades
+    let menu_open = *state.get() != MenuState::Closed;
+    if actions.just_pressed(Action::Pause) || (menu_open && actions.just_pressed(Action::Back)) {
+        let back = state.get().back(playing.is_some());
+        if back != *state.get() {
+            next_state.set(back);
+        }
+        return;
+    }
+
+    if *state.get() == MenuState::Closed {
+        return;
+    }
+
+    let count = buttons.iter().count();
+    if count == 0 {
+        return;
+    }
+    match input.vertical() {
+        -1 => focus.0 = (focus.0 + count - 1) % count,
+        1 => focus.0 = (focus.0 + 1) % count,
+        _ => {}
+    }
+
+    let mut activated = None;
+    for (interaction, button) in &interactions {
+        match interaction {
+            Interaction::Hovered => focus.0 = button.index,
+            Interaction::Pressed => activated = Some(button.action),
+            Interaction::None => {}
+        }
+    }
+    if input.confirm() {
+        activated = buttons
+            .iter()
+            .find(|button| button.index == focus.0)
+            .map(|button| button.action);
+    }
+    let Some(action) = activated else {
+        return;
+    };
+
+    let mut start = |commands: &mut Commands, event: StartGame| {
+        commands.insert_resource(Playing {
+            online: !matches!(event, StartGame::Offline),
+        });
+        events.start_game.send(event);
+        next_state.set(MenuState::Closed);
+    };
+    match action {
+        MenuAction::PlayOnline => start(&mut commands, StartGame::Online { room: None }),
+        MenuAction::Join if !room.0.is_empty() => start(
+            &mut commands,
+            StartGame::Online {
+                room: Some(room.0.clone()),
+            },
+        ),
+        MenuAction::Join => {}
+        MenuAction::PlayOffline => start(&mut commands, StartGame::Offline),
+        MenuAction::Ship => {
+            // the next unlocked one, the default ship is always unlocked
+            let ships = ShipType::ALL
+                .into_iter()
+                .filter(|ship| save.has_ship(*ship));
+            let current = settings.ship();
+            settings.ship = ships
+                .clone()
+                .skip_while(|ship| *ship != current)
+                .nth(1)
+                .or_else(|| ships.clone().next())
+                .unwrap_or_default();
+            settings.ship_override = None;
+        }
+        MenuAction::JoinRoom => next_state.set(MenuState::JoinRoom),
+        MenuAction::Settings => next_state.set(MenuState::Settings),
+        MenuAction::Name => next_state.set(MenuState::Name),
+        MenuAction::Volume => {
+            settings.volume = ((settings.volume * 10.0).round() + 1.0) % 11.0 / 10.0;
+        }
+        MenuAction::Fullscreen => settings.fullscreen = !settings.fullscreen,
+        MenuAction::Vsync => settings.vsync = !settings.vsync,
+        MenuAction::SharedCamera => settings.shared_camera = !settings.shared_camera,
+        MenuAction::FireMode => settings.fire_mode = settings.fire_mode.next(),
+        MenuAction::Controls => next_state.set(MenuState::Controls),
+        MenuAction::Rebind(action) => {
+            rebinding.action = Some(action);
+            rebinding.taken.clear();
+        }
+        MenuAction::ResetControls => {
+            settings.bindings = Bindings::default();
+            rebinding.taken.clear();
+        }
+        MenuAction::Resume => next_state.set(MenuState::Closed),
+        MenuAction::AddPlayer => joining.0 = true,
+        MenuAction::Export => events.export.send(ExportScoreboard),
+        // nothing tears a run down in place, so a fresh process starts at the main menu instead
+        MenuAction::MainMenu => match relaunch() {
+            Ok(()) => events.exit.send(AppExit),
+            Err(err) => warn!(%err, "failed to restart the game"),
+        },
+        MenuAction::Back => next_state.set(state.get().back(playing.is_some())),
+        MenuAction::Quit => events.exit.send(AppExit),
+    }
+}
+
+/// Starts the game again with the same command line
+fn relaunch() -> io::Result<()> {
+    let exe = env::current_exe()?;
+    Command::new(exe).args(env::args_os().skip(1)).spawn()?;
+    Ok(())
+}
+
+/// Adds a couch co-op player for the first gamepad to press a button that no local player has
+/// yet, [`Action::Back`] on a device already playing cancels
+fn join(
+    actions: Res<ActionState>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut joining: ResMut<Joining>,
+    players: Query<&LocalPlayer>,
+    mut add_player: EventWriter<AddLocalPlayer>,
+) {
+    // the press that picked the menu button doesn't count
+    if !joining.0 || joining.is_changed() {
+        return;
+    }
+
+    let gamepad = gamepad_buttons
+        .get_just_pressed()
+        .map(|button| button.gamepad)
+        .find(|gamepad| !players.iter().any(|local| local.gamepad == Some(*gamepad)));
+    if let Some(gamepad) = gamepad {
+        add_player.send(AddLocalPlayer { gamepad });
+        joining.0 = false;
+    } else if actions.just_pressed(Action::Back) {
+        joining.0 = false;
+    }
+}
+
+/// Binds the action picked on the controls screen to the next key, mouse or gamepad button,
+/// [`Action::Back`] cancels
+fn rebind(
+    actions: Res<ActionState>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Settings>,
+) {
+    // the press that picked the action doesn't count
+    let Some(action) = rebinding.action.filter(|_| !rebinding.is_changed()) else {
+        return;
+    };
+    if actions.just_pressed(Action::Back) {
+        rebinding.action = None;
+        return;
+    }
+
+    let binding = keys
+        .get_just_pressed()
+        .next()
+        .map(|key| Binding::Key(*key))
+        .or_else(|| {
+            mouse_buttons
+                .get_just_pressed()
+                .next()
+                .map(|button| Binding::Mouse(*button))
+        })
+        .or_else(|| {
+            gamepad_buttons
+                .get_just_pressed()
+                .next()
+                .map(|button| Binding::Gamepad(button.button_type))
+        });
+    if let Some(binding) = binding {
+        let taken_from = settings.bindings.set(action, binding);
+        rebinding.taken = taken_from
+            .into_iter()
+            .map(|other| (other, binding))
+            .collect();
+        rebinding.action = None;
+    }
+}
+
+/// Rebuilds the open menu whenever the screen or anything it shows changes
+fn spawn_menu(
+    mut commands: Commands,
+    font: Res<UiFont>,
+    state: Res<State<MenuState>>,
+    playing: Option<Res<Playing>>,
+    settings: Res<Settings>,
+    room: Res<RoomInput>,
+    name: Res<NameInput>,
+    rebinding: Res<Rebinding>,
+    joining: Res<Joining>,
+    recorder: Option<Res<Recorder>>,
+    mut focus: ResMut<MenuFocus>,
+    roots: Query<Entity, With<MenuRoot>>,
+    local_players: Query<&LocalPlayer>,
+    players: Query<(Ref<PlayerName>, &RunStats, Has<PlayerPeerId>)>,
+    mut roster_len: Local<usize>,
+) {
+    // the pause menu lists everyone playing, so it follows names and joins and leaves
+    let roster_changed =
+        players.iter().len() != *roster_len || players.iter().any(|(name, ..)| name.is_changed());
+    *roster_len = players.iter().len();
+    if !state.is_changed()
+        && !settings.is_changed()
+        && !room.is_changed()
+        && !name.is_changed()
+        && !rebinding.is_changed()
+        && !joining.is_changed()
+        && !roster_changed
+    {
+        return;
+    }
+    // our own lines first
+    let mut players = players.iter().collect::<Vec<_>>();
+    players.sort_by_key(|(_, _, remote)| *remote);
+    if state.is_changed() {
+        focus.0 = 0;
+    }
+    for root in &roots {
+        commands.entity(root).despawn_recursive();
+    }
+
+    let (title, mut actions, notes) = match state.get() {
+        MenuState::Main => (
+            "Bevy Jam 4".to_string(),
+            vec![
+                MenuAction::PlayOnline,
+                MenuAction::JoinRoom,
+                MenuAction::PlayOffline,
+                MenuAction::Ship,
+                MenuAction::Settings,
+                MenuAction::Quit,
+            ],
+            vec![format!("Flying as {}", settings.name())],
+        ),
+        MenuState::JoinRoom => (
+            format!("Room: {}_", room.0),
+            vec![MenuAction::Join, MenuAction::Ship, MenuAction::Back],
+            Vec::new(),
+        ),
+        MenuState::Settings => (
+            "Settings".to_string(),
+            vec![
+                MenuAction::Name,
+                MenuAction::Volume,
+                MenuAction::Fullscreen,
+                MenuAction::Vsync,
+                MenuAction::SharedCamera,
+                MenuAction::FireMode,
+                MenuAction::Controls,
+                MenuAction::Back,
+            ],
+            Vec::new(),
+        ),
+        MenuState::Controls => (
+            "Controls".to_string(),
+            Action::ALL
+                .into_iter()
+                .map(MenuAction::Rebind)
+                .chain([MenuAction::ResetControls, MenuAction::Back])
+                .collect(),
+            // a key or button only does one thing, so say where it went
+            rebinding
+                .taken
+                .iter()
+                .map(|(other, binding)| {
+                    format!("{} no longer does {}", binding.label(), other.label())
+                })
+                .chain(FIXED_CONTROLS.map(str::to_string))
+                .collect(),
+        ),
+        MenuState::Name => (
+            format!("Name: {}_", name.0),
+            vec![MenuAction::Back],
+            Vec::new(),
+        ),
+        MenuState::Paused => {
+            let online = playing.is_some_and(|playing| playing.online);
+            // couch co-op is offline only, and replays only know about the first player
+            let can_add_player =
+                !online && recorder.is_none() && local_players.iter().count() < MAX_LOCAL_PLAYERS;
+            let mut actions = vec![MenuAction::Resume];
+            if can_add_player {
+                actions.push(MenuAction::AddPlayer);
+            }
+            actions.extend([MenuAction::Settings, MenuAction::Quit]);
+            let mut notes = Vec::new();
+            if online {
+                notes.push("The game goes on for everyone else".to_string());
+            }
+            notes.push(format!("Players ({})", players.len()));
+            notes.extend(players.iter().map(|(name, _, remote)| {
+                if *remote {
+                    name.0.clone()
+                } else {
+                    format!("{} (here)", name.0)
+                }
+            }));
+            ("Paused".to_string(), actions, notes)
+        }
+        MenuState::GameOver => (
+            "Game over".to_string(),
+            vec![MenuAction::Export, MenuAction::MainMenu, MenuAction::Quit],
+            players
+                .into_iter()
+                .map(|(name, stats, _)| format!("{}: {}", name.0, stats.label()))
+                .collect(),
+        ),
+        MenuState::Closed => return,
+    };
+    // the recording already started with the ship picked before the menu opened, and starting
+    // over would write a new one over it
+    if recorder.is_some() {
+        actions.retain(|action| !matches!(action, MenuAction::Ship | MenuAction::MainMenu));
+    }
+
+    let text_style = |font_size| TextStyle {
+        font_size,
+        font: font.0.clone(),
+        ..default()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(8.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            MenuRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(title, text_style(50.0)));
+            // long lists like the controls go side by side to fit on the screen
+            let columns = actions.len().div_ceil(MENU_COLUMN_LENGTH);
+            let per_column = actions.len().div_ceil(columns).max(1);
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(8.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (column, actions) in actions.chunks(per_column).enumerate() {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    flex_direction: FlexDirection::Column,
+                                    row_gap: Val::Px(8.0),
+                                    ..default()
+                                },
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                for (row, &action) in actions.iter().enumerate() {
+                                    let index = column * per_column + row;
+                                    spawn_menu_button(
+                                        parent,
+                                        MenuButton { index, action },
+                                        menu_button_label(action, &settings, &rebinding, &joining),
+                                        text_style(30.0),
+                                    );
+                                }
+                            });
+                    }
+                });
+            for note in notes {
+                parent.spawn(TextBundle::from_section(note, text_style(20.0)));
+            }
+        });
+}
+
+fn menu_button_label(
+    action: MenuAction,
+    settings: &Settings,
+    rebinding: &Rebinding,
+    joining: &Joining,
+) -> String {
+    match action {
+        MenuAction::Rebind(action) if rebinding.action == Some(action) => {
+            format!("{}: press a button...", action.label())
+        }
+        MenuAction::AddPlayer if joining.0 => "Press a button on their gamepad...".to_string(),
+        _ => action.label(settings),
+    }
+}
+
+fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    button: MenuButton,
+    label: String,
+    text_style: TextStyle,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(320.0),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(label, text_style));
+        });
+}
+
+fn highlight_menu(focus: Res<MenuFocus>, mut buttons: Query<(&MenuButton, &mut BackgroundColor)>) {
+    for (button, mut background) in &mut buttons {
+        *background = if button.index == focus.0 {
+            Color::rgb(0.3, 0.3, 0.5).into()
+        } else {
+            Color::rgb(0.15, 0.15, 0.15).into()
+        };
+    }
+}
+
+/// Menus hold the game still unless someone else is playing it too, replays don't run this since
+/// the recording says when the menu paused
+fn pause_menu(
+    state: Res<State<MenuState>>,
+    playing: Option<Res<Playing>>,
+    mut paused: ResMut<Paused>,
+) {
+    let menu_open = *state.get() != MenuState::Closed;
+    let online = playing.is_some_and(|playing| playing.online);
+    paused.set(PauseReason::Menu, menu_open && !online);
+}
@@ -0,0 +1,515 @@
+use std::f32::consts::TAU;
+
+use bevy::{math::vec3, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bullet::{spawn_bullets, BulletSpawnEvent},
+    enemy::{DamageDealt, Enemy},
+    net::PlayerPeerId,
+    player::PlayerInput,
+    ship::Muzzles,
+    simulation::{GameRng, SimulationSet},
+    stats::{Stat, Stats},
+};
+
+pub const MAX_WEAPON_LEVEL: u32 = 8;
+const BLADE_RADIUS: f32 = 0.4;
+const BEAM_WIDTH: f32 = 0.5;
+const BEAM_DURATION: f32 = 0.1;
+const PULSE_DURATION: f32 = 0.3;
+
+pub struct WeaponPlugin;
+
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, startup).add_systems(
+            FixedUpdate,
+            (
+                fire_weapons.before(spawn_bullets),
+                update_blades,
+                update_effects,
+            )
+                .in_set(SimulationSet::Update),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WeaponType {
+    TwinBlaster,
+    Shotgun,
+    LaserBeam,
+    HomingMissiles,
+    OrbitingBlades,
+    AreaPulse,
+}
+
+/// Tuning for a weapon at a given level
+#[derive(Debug, Clone, Copy)]
+pub struct WeaponStats {
+    /// Seconds between shots
+    pub cooldown: f32,
+    /// Per hit, or per second for blades
+    pub damage: f32,
+    /// Projectiles per shot, or blades in orbit
+    pub count: u32,
+    /// Random deviation of each projectile, or the width of the fan for shotguns and missiles
+    pub spread: f32,
+    /// Projectile speed, or radians per second for blades
+    pub speed: f32,
+    /// Seconds a projectile lives
+    pub ttl: f32,
+    /// Reach of beams, blades and pulses
+    pub range: f32,
+}
+
+impl WeaponType {
+    pub const ALL: [WeaponType; 6] = [
+        WeaponType::TwinBlaster,
+        WeaponType::Shotgun,
+        WeaponType::LaserBeam,
+        WeaponType::HomingMissiles,
+        WeaponType::OrbitingBlades,
+        WeaponType::AreaPulse,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            WeaponType::TwinBlaster => "Twin blaster",
+            WeaponType::Shotgun => "Shotgun",
+            WeaponType::LaserBeam => "Laser beam",
+            WeaponType::HomingMissiles => "Homing missiles",
+            WeaponType::OrbitingBlades => "Orbiting blades",
+            WeaponType::AreaPulse => "Area pulse",
+        }
+    }
+
+    fn base_stats(self) -> WeaponStats {
+        match self {
+            WeaponType::TwinBlaster => WeaponStats {
+                cooldown: 0.1,
+                damage: 1.0,
+                count: 1,
+                spread: 0.1,
+                speed: 30.0,
+                ttl: 2.0,
+                range: 0.0,
+            },
+            WeaponType::Shotgun => WeaponStats {
+                cooldown: 0.8,
+                damage: 1.0,
+                count: 5,
+                spread: 0.6,
+                speed: 25.0,
+                ttl: 0.4,
+                range: 0.0,
+            },
+            WeaponType::LaserBeam => WeaponStats {
+                cooldown: 1.5,
+                damage: 3.0,
+                count: 1,
+                spread: 0.0,
+                speed: 0.0,
+                ttl: 0.0,
+                range: 15.0,
+            },
+            WeaponType::HomingMissiles => WeaponStats {
+                cooldown: 1.0,
+                damage: 2.0,
+                count: 1,
+                spread: 1.0,
+                speed: 15.0,
+                ttl: 3.0,
+                range: 0.0,
+            },
+            WeaponType::OrbitingBlades => WeaponStats {
+                cooldown: 0.0,
+                damage: 4.0,
+                count: 2,
+                spread: 0.0,
+                speed: 3.0,
+                ttl: 0.0,
+                range: 2.0,
+            },
+            WeaponType::AreaPulse => WeaponStats {
+                cooldown: 3.0,
+                damage: 2.0,
+                count: 1,
+                spread: 0.0,
+                speed: 0.0,
+                ttl: 0.0,
+                range: 4.0,
+            },
+        }
+    }
+
+    pub fn stats(self, level: u32) -> WeaponStats {
+        let mut stats = self.base_stats();
+        let bonus = level.saturating_sub(1);
+        stats.damage *= 1.0 + 0.25 * bonus as f32;
+        stats.cooldown *= 0.9_f32.powi(bonus as i32);
+        match self {
+            WeaponType::Shotgun => stats.count += bonus,
+            WeaponType::HomingMissiles | WeaponType::OrbitingBlades => stats.count += bonus / 2,
+            WeaponType::LaserBeam | WeaponType::AreaPulse => {
+                stats.range *= 1.0 + 0.1 * bonus as f32
+            }
+            WeaponType::TwinBlaster => {}
+        }
+        stats
+    }
+
+    /// Passive weapons fire on their own instead of waiting for the fire input
+    pub fn passive(self) -> bool {
+        matches!(self, WeaponType::OrbitingBlades | WeaponType::AreaPulse)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Weapon {
+    pub weapon_type: WeaponType,
+    pub level: u32,
+    cooldown: f32,
+    shots: u32,
+}
+
+impl Weapon {
+    pub fn new(weapon_type: WeaponType) -> Self {
+        Self {
+            weapon_type,
+            level: 1,
+            cooldown: 0.0,
+            shots: 0,
+        }
+    }
+
+    pub fn stats(&self) -> WeaponStats {
+        self.weapon_type.stats(self.level)
+    }
+}
+
+/// Everything a player has equipped, each weapon keeps its own cooldown
+#[derive(Component, Debug, Clone, Default)]
+pub struct Weapons(pub Vec<Weapon>);
+
+impl Weapons {
+    pub fn new(weapon_type: WeaponType) -> Self {
+        let mut weapons = Self::default();
+        weapons.add(weapon_type);
+        weapons
+    }
+
+    pub fn get(&self, weapon_type: WeaponType) -> Option<&Weapon> {
+        self.0.iter().find(|w| w.weapon_type == weapon_type)
+    }
+
+    /// Equips the weapon, or levels it up when it's already equipped
+    pub fn add(&mut self, weapon_type: WeaponType) {
+        match self.0.iter_mut().find(|w| w.weapon_type == weapon_type) {
+            Some(weapon) => weapon.level = (weapon.level + 1).min(MAX_WEAPON_LEVEL),
+            None => self.0.push(Weapon::new(weapon_type)),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Blade {
+    pub owner: Entity,
+    pub index: u32,
+}
+
+/// Short lived visual for beams and pulses
+#[derive(Component)]
+struct WeaponEffect {
+    ttl: f32,
+    duration: f32,
+    start_scale: Vec3,
+    end_scale: Vec3,
+}
+
+#[derive(Resource)]
+struct WeaponAssets {
+    beam_mesh: Handle<Mesh>,
+    beam_material: Handle<StandardMaterial>,
+    blade_mesh: Handle<Mesh>,
+    blade_material: Handle<StandardMaterial>,
+    pulse_mesh: Handle<Mesh>,
+    pulse_material: Handle<StandardMaterial>,
+}
+
+fn startup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(WeaponAssets {
+        beam_mesh: meshes.add(shape::Box::new(1.0, 0.1, 1.0).into()),
+        beam_material: materials.add(StandardMaterial {
+            base_color: Color::RED,
+            unlit: true,
+            ..default()
+        }),
+        blade_mesh: meshes.add(shape::Box::new(0.6, 0.1, 0.15).into()),
+        blade_material: materials.add(StandardMaterial {
+            base_color: Color::CYAN,
+            unlit: true,
+            ..default()
+        }),
+        pulse_mesh: meshes.add(shape::Circle::new(1.0).into()),
+        pulse_material: materials.add(StandardMaterial {
+            base_color: Color::rgba(0.3, 0.8, 1.0, 0.3),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+fn fire_weapons(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<WeaponAssets>,
+    mut rng: ResMut<GameRng>,
+    mut players: Query<
+        (
+            Entity,
+            &Stats,
+            &PlayerInput,
+            &Transform,
+            &Muzzles,
+            &mut Weapons,
+        ),
+        (Without<PlayerPeerId>, Without<Enemy>),
+    >,
+    mut enemies: Query<(&Transform, &mut Enemy, &Visibility)>,
+    mut bullet_events: EventWriter<BulletSpawnEvent>,
+    mut damage_events: EventWriter<DamageDealt>,
+) {
+    let dt = time.delta_seconds();
+
+    for (player, player_stats, input, transform, muzzles, mut weapons) in players.iter_mut() {
+        let forward = (transform.forward() * Vec3::new(1.0, 0.0, 1.0))
+            .normalize_or_zero()
+            .xz();
+
+        for weapon in weapons.0.iter_mut() {
+            weapon.cooldown -= dt;
+            if weapon.cooldown > 0.0 || !(input.fire || weapon.weapon_type.passive()) {
+                continue;
+            }
+
+            let mut stats = weapon.stats();
+            stats.damage *= player_stats.get(Stat::Damage);
+            weapon.cooldown = stats.cooldown / player_stats.get(Stat::FireRate);
+            weapon.shots += 1;
+            // alternate between the ship's guns, pulses go off around all of them
+            let muzzle = match weapon.weapon_type {
+                WeaponType::AreaPulse => transform.transform_point(muzzles.center()),
+                _ => transform.transform_point(muzzles.get(weapon.shots)),
+            };
+            let position = muzzle.xz();
+
+            match weapon.weapon_type {
+                WeaponType::TwinBlaster => {
+                    let spread = Vec2::new(rng.spread.f32() - 0.5, rng.spread.f32() - 0.5);
+                    bullet_events.send(BulletSpawnEvent {
+                        position,
+                        velocity: forward + spread * stats.spread,
+                        speed: stats.speed,
+                        damage: stats.damage,
+                        ttl: stats.ttl,
+                        homing: false,
+                        owner: Some(player),
+                    });
+                }
+                WeaponType::Shotgun | WeaponType::HomingMissiles => {
+                    for i in 0..stats.count {
+                        let t = if stats.count > 1 {
+                            i as f32 / (stats.count - 1) as f32 - 0.5
+                        } else {
+                            0.0
+                        };
+                        let angle = t * stats.spread;
+                        bullet_events.send(BulletSpawnEvent {
+                            position,
+                            velocity: Vec2::from_angle(angle).rotate(forward),
+                            speed: stats.speed,
+                            damage: stats.damage,
+                            ttl: stats.ttl,
+                            homing: weapon.weapon_type == WeaponType::HomingMissiles,
+                            owner: Some(player),
+                        });
+                    }
+                }
+                WeaponType::LaserBeam => {
+                    for (enemy_transform, mut enemy, visibility) in enemies.iter_mut() {
+                        if *visibility == Visibility::Hidden {
+                            continue;
+                        }
+                        let to_enemy = enemy_transform.translation.xz() - position;
+                        let along = to_enemy.dot(forward);
+                        let across = to_enemy.perp_dot(forward).abs();
+                        if (0.0..stats.range).contains(&along) && across < BEAM_WIDTH {
+                            enemy.damage(
+                                stats.damage,
+                                enemy_transform.translation,
+                                Some(player),
+                                &mut damage_events,
+                            );
+                        }
+                    }
+
+                    let scale = Vec3::new(BEAM_WIDTH * 0.5, 1.0, stats.range);
+                    commands.spawn((
+                        PbrBundle {
+                            mesh: assets.beam_mesh.clone(),
+                            material: assets.beam_material.clone(),
+                            transform: Transform::from_translation(
+                                muzzle + vec3(forward.x, 0.0, forward.y) * stats.range * 0.5,
+                            )
+                            .looking_to(vec3(forward.x, 0.0, forward.y), Vec3::Y)
+                            .with_scale(scale),
+                            ..default()
+                        },
+                        WeaponEffect {
+                            ttl: BEAM_DURATION,
+                            duration: BEAM_DURATION,
+                            start_scale: scale,
+                            end_scale: scale * Vec3::new(0.0, 1.0, 1.0),
+                        },
+                    ));
+                }
+                WeaponType::AreaPulse => {
+                    for (enemy_transform, mut enemy, visibility) in enemies.iter_mut() {
+                        let distance = enemy_transform.translation.xz().distance(position);
+                        if *visibility != Visibility::Hidden && distance < stats.range {
+                            enemy.damage(
+                                stats.damage,
+                                enemy_transform.translation,
+                                Some(player),
+                                &mut damage_events,
+                            );
+                        }
+                    }
+
+                    commands.spawn((
+                        PbrBundle {
+                            mesh: assets.pulse_mesh.clone(),
+                            material: assets.pulse_material.clone(),
+                            transform: Transform::from_translation(muzzle)
+                                .with_rotation(Quat::from_rotation_x(-TAU / 4.0))
+                                .with_scale(Vec3::ZERO),
+                            ..default()
+                        },
+                        WeaponEffect {
+                            ttl: PULSE_DURATION,
+                            duration: PULSE_DURATION,
+                            start_scale: Vec3::ZERO,
+                            end_scale: Vec3::splat(stats.range),
+                        },
+                    ));
+                }
+                // blades are always out, see update_blades
+                WeaponType::OrbitingBlades => {}
+            }
+        }
+    }
+}
+
+fn update_blades(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<WeaponAssets>,
+    players: Query<(Entity, &Transform, &Weapons, &Stats), Without<Blade>>,
+    mut blades: Query<(Entity, &Blade, &mut Transform), Without<Enemy>>,
+    mut enemies: Query<(&Transform, &mut Enemy, &Visibility), (Without<Blade>, Without<Weapons>)>,
+    mut damage_events: EventWriter<DamageDealt>,
+) {
+    let dt = time.delta_seconds();
+
+    for (entity, blade, _) in blades.iter() {
+        let count = players
+            .get(blade.owner)
+            .ok()
+            .and_then(|(_, _, weapons, _)| weapons.get(WeaponType::OrbitingBlades))
+            .map(|weapon| weapon.stats().count)
+            .unwrap_or(0);
+        if blade.index >= count {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for (player_entity, player_transform, weapons, player_stats) in players.iter() {
+        let Some(weapon) = weapons.get(WeaponType::OrbitingBlades) else {
+            continue;
+        };
+        let mut stats = weapon.stats();
+        stats.damage *= player_stats.get(Stat::Damage);
+
+        let existing = blades
+            .iter()
+            .filter(|(_, blade, _)| blade.owner == player_entity)
+            .map(|(_, blade, _)| blade.index)
+            .collect::<Vec<_>>();
+        for index in (0..stats.count).filter(|i| !existing.contains(i)) {
+            commands.spawn((
+                PbrBundle {
+                    mesh: assets.blade_mesh.clone(),
+                    material: assets.blade_material.clone(),
+                    transform: *player_transform,
+                    ..default()
+                },
+                Blade {
+                    owner: player_entity,
+                    index,
+                },
+            ));
+        }
+
+        for (_, blade, mut transform) in blades.iter_mut() {
+            if blade.owner != player_entity || blade.index >= stats.count {
+                continue;
+            }
+
+            let angle = time.elapsed_seconds() * stats.speed
+                + blade.index as f32 / stats.count as f32 * TAU;
+            let offset = Quat::from_rotation_y(angle) * Vec3::X * stats.range;
+            transform.translation = player_transform.translation + offset;
+            transform.rotation = Quat::from_rotation_y(angle);
+
+            for (enemy_transform, mut enemy, visibility) in enemies.iter_mut() {
+                let distance = enemy_transform
+                    .translation
+                    .xz()
+                    .distance(transform.translation.xz());
+                if *visibility != Visibility::Hidden && distance < BLADE_RADIUS {
+                    // a hit every tick, the damage numbers add these up
+                    enemy.damage(
+                        stats.damage * dt,
+                        enemy_transform.translation,
+                        Some(player_entity),
+                        &mut damage_events,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn update_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effects: Query<(Entity, &mut WeaponEffect, &mut Transform)>,
+) {
+    for (entity, mut effect, mut transform) in effects.iter_mut() {
+        effect.ttl -= time.delta_seconds();
+        if effect.ttl <= 0.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let t = 1.0 - effect.ttl / effect.duration;
+        transform.scale = effect.start_scale.lerp(effect.end_scale, t);
+    }
+}